@@ -0,0 +1,104 @@
+use std::{env, time::Instant};
+
+use tlsh::{BucketKind, ChecksumKind, DigestSet, Tlsh, TlshBuilder, Version};
+
+/// In this example, we will compare a batch of queries against a collection of digests, once by
+/// looping over a ```Vec<Tlsh>``` and once with a ```DigestSet```, and report the time taken.
+///
+/// Usage: ```cargo run --release --example digest_set_scan [digests] [queries] [threshold]```
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let arg = |idx: usize, default: usize| {
+        args.get(idx)
+            .map(|a| a.parse().expect("Arguments must be integers"))
+            .unwrap_or(default)
+    };
+    let (count, query_count, threshold) = (arg(1, 100_000), arg(2, 100), arg(3, 100));
+    assert!(
+        count > 0 && query_count > 0,
+        "Numbers of digests and queries must be positive"
+    );
+
+    // Queries are taken from the digests, repeating them if there are more queries than digests.
+    let digests = generate(count);
+    let queries: Vec<&Tlsh> = digests
+        .iter()
+        .cycle()
+        .step_by((count / query_count).max(1))
+        .take(query_count)
+        .collect();
+    let set = DigestSet::from_digests(BucketKind::Bucket128, ChecksumKind::OneByte, &digests)
+        .expect("All digests have the same configuration");
+
+    let now = Instant::now();
+    let mut loop_hits = 0;
+    for query in &queries {
+        loop_hits += digests
+            .iter()
+            .filter(|t| query.diff(t, true) <= threshold)
+            .count();
+    }
+    let loop_time = now.elapsed();
+
+    let now = Instant::now();
+    let mut scan_hits = 0;
+    for query in &queries {
+        scan_hits += set.scan(query, threshold, true).unwrap().len();
+    }
+    let scan_time = now.elapsed();
+
+    assert_eq!(loop_hits, scan_hits);
+    let comparisons = (queries.len() * digests.len()) as f64;
+    println!(
+        "{} queries against {} digests, threshold {}, {} hits",
+        queries.len(),
+        digests.len(),
+        threshold,
+        scan_hits
+    );
+    println!(
+        "Tlsh::diff loop:   {:>10.2?} ({:.1} ns per comparison)",
+        loop_time,
+        loop_time.as_nanos() as f64 / comparisons
+    );
+    println!(
+        "DigestSet::scan:   {:>10.2?} ({:.1} ns per comparison)",
+        scan_time,
+        scan_time.as_nanos() as f64 / comparisons
+    );
+}
+
+/// Generates digests of pseudo-random inputs, in groups of similar inputs.
+fn generate(count: usize) -> Vec<Tlsh> {
+    let mut seed = 42u32;
+    let mut next = move || {
+        seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+        (seed >> 16) as usize
+    };
+
+    let mut result = Vec::with_capacity(count);
+    let mut base: Vec<u8> = Vec::new();
+    while result.len() < count {
+        if result.len() % 16 == 0 {
+            base = (0..512).map(|_| next() as u8).collect();
+        }
+
+        let mut data = base.clone();
+        for _ in 0..(next() % 32) {
+            let pos = next() % data.len();
+            data[pos] = next() as u8;
+        }
+
+        let mut builder = TlshBuilder::new(
+            BucketKind::Bucket128,
+            ChecksumKind::OneByte,
+            Version::Version4,
+        );
+        builder.update(&data);
+        if let Ok(tlsh) = builder.build() {
+            result.push(tlsh);
+        }
+    }
+
+    result
+}
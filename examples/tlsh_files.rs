@@ -1,20 +1,13 @@
-use std::{
-    collections::HashMap,
-    env,
-    fs::{read_dir, File},
-    io::{BufReader, Read},
-    path::Path,
-};
+use std::{env, fs::read_dir, path::Path};
 
-use tlsh::TlshBuilder;
+use tlsh::{DistanceMatrix, Tlsh};
 
 /// In this example, we will compute the hash values for all files in a directory.
 fn main() {
     let args: Vec<String> = env::args().collect();
     let dir = Path::new(args.get(1).unwrap());
-    let mut buffer = [0; 1024];
 
-    let mut hm = HashMap::new();
+    let mut files = Vec::new();
 
     for entry in read_dir(dir).unwrap() {
         assert!(
@@ -27,42 +20,31 @@ fn main() {
         let pb = d.path();
 
         if pb.is_file() {
-            let mut builder = TlshBuilder::new(
+            let tlsh = Tlsh::from_path(
                 tlsh::BucketKind::Bucket128,
                 tlsh::ChecksumKind::ThreeByte,
                 tlsh::Version::Version4,
-            );
-            let file = File::open(&pb).unwrap();
-            let mut reader = BufReader::new(file);
+                &pb,
+            )
+            .unwrap_or_else(|e| panic!("Failed to hash file {:?}: {}", &pb, e));
 
-            let mut byte_read = 1;
-            while byte_read != 0 {
-                match reader.read(&mut buffer) {
-                    Ok(n) => {
-                        byte_read = n;
-                        builder.update_from(&buffer, 0, byte_read);
-                    }
-                    Err(_) => panic!("Failed to read file: {:?}", &pb),
-                };
-            }
-
-            hm.insert(
-                pb.as_os_str().to_os_string().into_string().unwrap(),
-                builder.build().unwrap(),
-            );
+            files.push((pb.as_os_str().to_os_string().into_string().unwrap(), tlsh));
         }
     }
 
-    for (p1, tlsh1) in &hm {
+    let digests: Vec<Tlsh> = files.iter().map(|(_, tlsh)| tlsh.clone()).collect();
+    let matrix = DistanceMatrix::new(&digests, true).unwrap();
+
+    for (ii, (p1, tlsh1)) in files.iter().enumerate() {
         println!("++ File: {}", p1);
         println!("   Hash: {}", tlsh1.hash());
 
-        for (p2, tlsh2) in &hm {
-            if p1 == p2 {
+        for (jj, (p2, _)) in files.iter().enumerate() {
+            if ii == jj {
                 continue;
             }
 
-            println!("   diff with {}: {}", p2, tlsh1.diff(tlsh2, true));
+            println!("   diff with {}: {}", p2, matrix.get(ii, jj));
         }
     }
 }
@@ -0,0 +1,72 @@
+use std::{
+    io::{self, IoSlice},
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use crate::{BucketKind, ChecksumKind, Tlsh, TlshBuilder, Version};
+
+/// Processes all written data as input stream. Writing is always ready and never fails.
+impl AsyncWrite for TlshBuilder {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.get_mut().update(buf);
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Poll::Ready(io::Write::write_vectored(self.get_mut(), bufs))
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        true
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl TlshBuilder {
+    /// Asynchronously reads all data from a reader until EOF and processes it. Returns the number
+    /// of bytes read.
+    pub async fn update_from_async_reader<R>(&mut self, reader: &mut R) -> io::Result<u64>
+    where
+        R: AsyncRead + Unpin + ?Sized,
+    {
+        tokio::io::copy(reader, self).await
+    }
+}
+
+impl Tlsh {
+    /// Asynchronously reads all data from a reader until EOF and computes its digest.
+    ///
+    /// If the digest cannot be built, the returned [`io::Error`] has the kind
+    /// [`io::ErrorKind::InvalidData`] and wraps the [`TlshError`](crate::TlshError).
+    pub async fn from_async_reader<R>(
+        bucket: BucketKind,
+        checksum: ChecksumKind,
+        ver: Version,
+        mut reader: R,
+    ) -> io::Result<Self>
+    where
+        R: AsyncRead + Unpin,
+    {
+        let mut builder = TlshBuilder::new(bucket, checksum, ver);
+        builder.update_from_async_reader(&mut reader).await?;
+        Ok(builder.build()?)
+    }
+}
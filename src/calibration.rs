@@ -0,0 +1,272 @@
+use crate::{helper::check_digest, BucketKind, ChecksumKind, Tlsh, TlshError};
+
+/// Estimated false positive and detection rates for all distances up to ```max_distance```.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RateEstimate {
+    /// Largest distance of the band (inclusive).
+    pub max_distance: usize,
+    /// Fraction of dissimilar pairs whose distance lies within the band or below.
+    pub false_positive_rate: f64,
+    /// Fraction of similar pairs whose distance lies within the band or below.
+    pub detection_rate: f64,
+}
+
+/// A table mapping distances of a digest configuration to estimated false positive and detection
+/// rates, organised in bands of distances.
+///
+/// A table is recomputed from labelled data via [`Calibration::distance_table`] or constructed from
+/// known bands via [`DistanceTable::new`]. The crate does not ship precomputed tables.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DistanceTable {
+    bucket_kind: BucketKind,
+    checksum_kind: ChecksumKind,
+    bands: Vec<RateEstimate>,
+}
+
+impl DistanceTable {
+    /// Constructs a table for the given configuration. Bands are sorted by their maximal distance.
+    pub fn new(
+        bucket_kind: BucketKind,
+        checksum_kind: ChecksumKind,
+        mut bands: Vec<RateEstimate>,
+    ) -> Self {
+        bands.sort_by_key(|b| b.max_distance);
+        Self {
+            bucket_kind,
+            checksum_kind,
+            bands,
+        }
+    }
+
+    /// Returns the number of buckets of the digests the table applies to.
+    pub fn bucket_kind(&self) -> BucketKind {
+        self.bucket_kind
+    }
+
+    /// Returns the checksum length of the digests the table applies to.
+    pub fn checksum_kind(&self) -> ChecksumKind {
+        self.checksum_kind
+    }
+
+    /// Returns all bands in ascending order.
+    pub fn bands(&self) -> &[RateEstimate] {
+        &self.bands
+    }
+
+    /// Computes the distance between two digests and returns the estimate of the band containing
+    /// it, or ```None``` if the distance exceeds all bands. ```with_len``` must match the setting
+    /// the table was computed with.
+    ///
+    /// Returns an error if a digest has a different configuration than the table.
+    pub fn estimate(
+        &self,
+        left: &Tlsh,
+        right: &Tlsh,
+        with_len: bool,
+    ) -> Result<Option<RateEstimate>, TlshError> {
+        check_digest(left, self.bucket_kind, self.checksum_kind)?;
+        check_digest(right, self.bucket_kind, self.checksum_kind)?;
+
+        Ok(self.estimate_distance(left.diff(right, with_len)))
+    }
+
+    /// Returns the estimate of the band containing ```distance```, i.e. the rates when using
+    /// ```distance``` as threshold. Returns ```None``` if the distance exceeds all bands.
+    ///
+    /// The distance must have been computed from digests with the configuration of the table,
+    /// which is not checked. See [`DistanceTable::estimate`] for a checked version.
+    pub fn estimate_distance(&self, distance: usize) -> Option<RateEstimate> {
+        self.bands
+            .iter()
+            .find(|b| distance <= b.max_distance)
+            .copied()
+    }
+}
+
+/// Confusion matrix and rates of a threshold: a pair is classified as similar if its distance does
+/// not exceed the threshold.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ThresholdReport {
+    /// Distance threshold (inclusive).
+    pub threshold: usize,
+    /// Number of similar pairs classified as similar.
+    pub true_positives: usize,
+    /// Number of dissimilar pairs classified as similar.
+    pub false_positives: usize,
+    /// Number of dissimilar pairs classified as dissimilar.
+    pub true_negatives: usize,
+    /// Number of similar pairs classified as dissimilar.
+    pub false_negatives: usize,
+}
+
+impl ThresholdReport {
+    /// Fraction of dissimilar pairs classified as similar. Zero if there are no dissimilar pairs.
+    pub fn false_positive_rate(&self) -> f64 {
+        ratio(
+            self.false_positives,
+            self.false_positives + self.true_negatives,
+        )
+    }
+
+    /// Fraction of similar pairs classified as similar. Zero if there are no similar pairs.
+    pub fn detection_rate(&self) -> f64 {
+        ratio(
+            self.true_positives,
+            self.true_positives + self.false_negatives,
+        )
+    }
+
+    /// Fraction of pairs classified as similar which are actually similar. Zero if no pair is
+    /// classified as similar.
+    pub fn precision(&self) -> f64 {
+        ratio(
+            self.true_positives,
+            self.true_positives + self.false_positives,
+        )
+    }
+}
+
+/// Labelled distances used to evaluate and choose distance thresholds.
+#[derive(Clone, Debug, Default)]
+pub struct Calibration {
+    // Sorted by distance.
+    pairs: Vec<(usize, bool)>,
+    positives: usize,
+}
+
+impl Calibration {
+    /// Constructs a calibration from pairs of distance and label, where the label states whether
+    /// the pair is similar (e.g. belongs to the same malware family).
+    pub fn new<I>(pairs: I) -> Self
+    where
+        I: IntoIterator<Item = (usize, bool)>,
+    {
+        let mut pairs: Vec<(usize, bool)> = pairs.into_iter().collect();
+        pairs.sort_unstable();
+        let positives = pairs.iter().filter(|p| p.1).count();
+
+        Self { pairs, positives }
+    }
+
+    /// Constructs a calibration from labelled pairs of digests. Returns an error if a pair cannot
+    /// be compared (see [`Tlsh::try_diff`]).
+    pub fn from_digests<'a, I>(pairs: I, with_len: bool) -> Result<Self, TlshError>
+    where
+        I: IntoIterator<Item = (&'a Tlsh, &'a Tlsh, bool)>,
+    {
+        let pairs = pairs
+            .into_iter()
+            .map(|(x, y, similar)| Ok((x.try_diff(y, with_len)?, similar)))
+            .collect::<Result<Vec<_>, TlshError>>()?;
+
+        Ok(Self::new(pairs))
+    }
+
+    /// Returns the number of labelled pairs.
+    pub fn len(&self) -> usize {
+        self.pairs.len()
+    }
+
+    /// Returns ```true``` if there are no labelled pairs.
+    pub fn is_empty(&self) -> bool {
+        self.pairs.is_empty()
+    }
+
+    /// Evaluates the given threshold on the labelled pairs.
+    pub fn report(&self, threshold: usize) -> ThresholdReport {
+        let below = self.pairs.partition_point(|p| p.0 <= threshold);
+        let true_positives = self.pairs[..below].iter().filter(|p| p.1).count();
+        let false_positives = below - true_positives;
+        let negatives = self.pairs.len() - self.positives;
+
+        ThresholdReport {
+            threshold,
+            true_positives,
+            false_positives,
+            true_negatives: negatives - false_positives,
+            false_negatives: self.positives - true_positives,
+        }
+    }
+
+    /// Computes the ROC curve, i.e. one report for each distinct distance of the labelled pairs in
+    /// ascending order.
+    pub fn roc_curve(&self) -> Vec<ThresholdReport> {
+        let mut result: Vec<ThresholdReport> = Vec::new();
+        let negatives = self.pairs.len() - self.positives;
+        let (mut tp, mut fp) = (0, 0);
+
+        for (ii, &(distance, similar)) in self.pairs.iter().enumerate() {
+            if similar {
+                tp += 1;
+            } else {
+                fp += 1;
+            }
+
+            // Emit a point only after the last pair with the same distance.
+            if self.pairs.get(ii + 1).map(|p| p.0) != Some(distance) {
+                result.push(ThresholdReport {
+                    threshold: distance,
+                    true_positives: tp,
+                    false_positives: fp,
+                    true_negatives: negatives - fp,
+                    false_negatives: self.positives - tp,
+                });
+            }
+        }
+
+        result
+    }
+
+    /// Chooses the largest threshold whose false positive rate does not exceed
+    /// ```max_false_positive_rate``` and returns its report. Returns ```None``` if no distance of
+    /// the labelled pairs satisfies the constraint.
+    pub fn choose_threshold(&self, max_false_positive_rate: f64) -> Option<ThresholdReport> {
+        self.roc_curve()
+            .into_iter()
+            .take_while(|r| r.false_positive_rate() <= max_false_positive_rate)
+            .last()
+    }
+
+    /// Recomputes a distance table from the labelled pairs with bands of the given width, i.e.
+    /// bands end at distances ```band_width - 1```, ```2 * band_width - 1``` and so on, up to the
+    /// largest labelled distance.
+    ///
+    /// # Panics
+    /// Panics if ```band_width``` is zero.
+    pub fn distance_table(
+        &self,
+        bucket_kind: BucketKind,
+        checksum_kind: ChecksumKind,
+        band_width: usize,
+    ) -> DistanceTable {
+        assert!(band_width > 0, "Band width must be positive");
+
+        let mut bands = Vec::new();
+        if let Some(&(max, _)) = self.pairs.last() {
+            let mut end = band_width - 1;
+            loop {
+                let report = self.report(end);
+                bands.push(RateEstimate {
+                    max_distance: end,
+                    false_positive_rate: report.false_positive_rate(),
+                    detection_rate: report.detection_rate(),
+                });
+
+                if end >= max {
+                    break;
+                }
+                end += band_width;
+            }
+        }
+
+        DistanceTable::new(bucket_kind, checksum_kind, bands)
+    }
+}
+
+fn ratio(x: usize, total: usize) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        x as f64 / total as f64
+    }
+}
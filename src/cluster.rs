@@ -0,0 +1,609 @@
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap, VecDeque},
+};
+
+use crate::{matrix::check_bucket_kinds, Tlsh, TlshError, VpTree};
+
+/// Maximal number of members considered as representative of a cluster.
+const MEDOID_CANDIDATES: usize = 64;
+
+/// The result of clustering a collection of digests.
+///
+/// Clusters are numbered from zero in the order of their first member, so the numbering only
+/// depends on the order of the input. Digests which belong to no cluster are labelled as noise.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Clustering {
+    labels: Vec<Option<usize>>,
+    sizes: Vec<usize>,
+    representatives: Vec<usize>,
+}
+
+impl Clustering {
+    /// Constructs a clustering from arbitrary cluster ids per digest, renumbering clusters in the
+    /// order of their first member and choosing a representative for each cluster.
+    pub(crate) fn from_labels(labels: &[Option<usize>], digests: &[Tlsh], with_len: bool) -> Self {
+        Self::with_representatives(labels, |members| medoid(members, digests, with_len))
+    }
+
+    /// Same as [`Clustering::from_labels`], but ```representative``` chooses the representative
+    /// of each cluster from its members.
+    pub(crate) fn with_representatives<F>(labels: &[Option<usize>], representative: F) -> Self
+    where
+        F: FnMut(&Vec<usize>) -> usize,
+    {
+        let mut mapping = HashMap::new();
+        let labels: Vec<Option<usize>> = labels
+            .iter()
+            .map(|label| {
+                label.map(|l| {
+                    let next = mapping.len();
+                    *mapping.entry(l).or_insert(next)
+                })
+            })
+            .collect();
+
+        let mut members = vec![Vec::new(); mapping.len()];
+        for (ii, label) in labels.iter().enumerate() {
+            if let Some(l) = label {
+                members[*l].push(ii);
+            }
+        }
+
+        Self {
+            sizes: members.iter().map(|m| m.len()).collect(),
+            representatives: members.iter().map(representative).collect(),
+            labels,
+        }
+    }
+
+    /// Returns the number of clustered digests, including noise.
+    pub fn len(&self) -> usize {
+        self.labels.len()
+    }
+
+    /// Returns ```true``` if no digests were clustered.
+    pub fn is_empty(&self) -> bool {
+        self.labels.is_empty()
+    }
+
+    /// Returns the number of clusters.
+    pub fn cluster_count(&self) -> usize {
+        self.sizes.len()
+    }
+
+    /// Returns the cluster of each digest, or ```None``` for noise.
+    pub fn labels(&self) -> &[Option<usize>] {
+        &self.labels
+    }
+
+    /// Returns the cluster of the digest at position ```idx```, or ```None``` for noise.
+    ///
+    /// # Panics
+    /// Panics if ```idx``` is out of bounds.
+    pub fn label(&self, idx: usize) -> Option<usize> {
+        self.labels[idx]
+    }
+
+    /// Returns the number of members of each cluster.
+    pub fn sizes(&self) -> &[usize] {
+        &self.sizes
+    }
+
+    /// Returns the position of the representative digest of each cluster.
+    ///
+    /// The representative is the member with the smallest sum of distances to all other members.
+    /// For clusters with more than 64 members, only 64 evenly spaced members are considered as
+    /// representative, so that choosing it takes linear time in the cluster size. [`HacT`] uses
+    /// the centres of its clusters instead.
+    pub fn representatives(&self) -> &[usize] {
+        &self.representatives
+    }
+
+    /// Returns the positions of all members of a cluster in ascending order.
+    pub fn members(&self, cluster: usize) -> Vec<usize> {
+        self.positions(Some(cluster))
+    }
+
+    /// Returns the positions of all digests labelled as noise in ascending order.
+    pub fn noise(&self) -> Vec<usize> {
+        self.positions(None)
+    }
+
+    fn positions(&self, label: Option<usize>) -> Vec<usize> {
+        self.labels
+            .iter()
+            .enumerate()
+            .filter(|(_, l)| **l == label)
+            .map(|(ii, _)| ii)
+            .collect()
+    }
+}
+
+/// Hierarchical agglomerative clustering for TLSH (HAC-T), based on J. Oliver, M. Ali and J.
+/// Hagen (2020), "HAC-T and Fast Search for Similarity in Security".
+///
+/// Each cluster has a centre, and every member lies within the cutoff of the centre of its
+/// cluster, so that the radius of all clusters is bounded by the cutoff. Clusters are built in
+/// two phases, which use [`VpTree`]s for nearest neighbour queries instead of comparing all pairs
+/// of digests:
+/// 1. Each digest is linked with its nearest neighbour if their distance does not exceed the
+///    cutoff. Links are processed in ascending order of their distance, and the clusters of both
+///    digests are merged if all members of one cluster lie within the cutoff of the centre of the
+///    other cluster, which becomes the centre of the merged cluster.
+/// 2. Each centre is linked with the nearest other centre if their distance does not exceed the
+///    cutoff, and the links are processed as in the first phase. This phase is repeated until no
+///    clusters are merged anymore.
+///
+/// Every digest is assigned to a cluster; digests which cannot join any other digest form
+/// clusters of size one. The representatives of the result are the centres of the clusters.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct HacT {
+    cutoff: usize,
+    with_len: bool,
+}
+
+impl HacT {
+    /// Constructs a clustering with the given distance cutoff. ```with_len``` determines whether
+    /// the length difference is considered in all distances (see [`Tlsh::diff`]).
+    pub fn new(cutoff: usize, with_len: bool) -> Self {
+        Self { cutoff, with_len }
+    }
+
+    /// Clusters the given digests.
+    ///
+    /// Returns an error if the digests have different [`BucketKind`](crate::BucketKind)s.
+    pub fn cluster(&self, digests: &[Tlsh]) -> Result<Clustering, TlshError> {
+        let tree = VpTree::new(
+            digests.iter().cloned().enumerate().map(|(ii, t)| (t, ii)),
+            self.with_len,
+        )?;
+
+        let mut clusters = CentredClusters::new(digests, self.cutoff, self.with_len);
+        let mut links = Vec::new();
+        for (ii, tlsh) in digests.iter().enumerate() {
+            if let Some((distance, jj)) = nearest_other(&tree, tlsh, ii, self.cutoff)? {
+                links.push((distance, ii, jj));
+            }
+        }
+        clusters.merge(links);
+
+        loop {
+            let centres = clusters.centres();
+            let tree = VpTree::new(
+                centres.iter().map(|&c| (digests[c].clone(), c)),
+                self.with_len,
+            )?;
+
+            let mut links = Vec::new();
+            for &c in &centres {
+                if let Some((distance, other)) = nearest_other(&tree, &digests[c], c, self.cutoff)?
+                {
+                    links.push((distance, c, other));
+                }
+            }
+
+            if !clusters.merge(links) {
+                return Ok(clusters.into_clustering());
+            }
+        }
+    }
+}
+
+/// Density-based clustering (DBSCAN), following M. Ester, H.-P. Kriegel, J. Sander and X. Xu
+/// (1996), "A Density-Based Algorithm for Discovering Clusters in Large Spatial Databases with
+/// Noise".
+///
+/// A digest is a core digest if at least ```min_points``` digests, including itself, lie within
+/// distance ```epsilon```. Clusters consist of core digests which are connected via neighbours
+/// within ```epsilon``` and of all other digests within ```epsilon``` of one of their core digests.
+/// All remaining digests are labelled as noise.
+///
+/// Digests are processed in the order of the input, so results are deterministic. A digest
+/// within ```epsilon``` of core digests of several clusters is assigned to the cluster found
+/// first.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Dbscan {
+    epsilon: usize,
+    min_points: usize,
+    with_len: bool,
+    use_index: bool,
+}
+
+impl Dbscan {
+    /// Constructs a clustering with the given parameters. ```with_len``` determines whether the
+    /// length difference is considered in all distances (see [`Tlsh::diff`]).
+    pub fn new(epsilon: usize, min_points: usize, with_len: bool) -> Self {
+        Self {
+            epsilon,
+            min_points,
+            with_len,
+            use_index: true,
+        }
+    }
+
+    /// Sets whether neighbours are found with a [`VpTree`] or by comparing all pairs of digests.
+    /// Both give the same results, but the tree is faster unless ```epsilon``` is large.
+    /// Default: ```true```.
+    pub fn with_index(mut self, use_index: bool) -> Self {
+        self.use_index = use_index;
+        self
+    }
+
+    /// Clusters the given digests.
+    ///
+    /// Returns an error if the digests have different [`BucketKind`](crate::BucketKind)s.
+    pub fn cluster(&self, digests: &[Tlsh]) -> Result<Clustering, TlshError> {
+        let neighbourhoods = Neighbourhoods::new(digests, self.with_len, self.use_index)?;
+        let mut labels: Vec<Option<usize>> = vec![None; digests.len()];
+        let mut visited = vec![false; digests.len()];
+        let mut cluster = 0;
+
+        for ii in 0..digests.len() {
+            if visited[ii] {
+                continue;
+            }
+            visited[ii] = true;
+
+            let neighbours = neighbourhoods.get(ii, self.epsilon);
+            if neighbours.len() < self.min_points {
+                continue;
+            }
+
+            labels[ii] = Some(cluster);
+            let mut queue: VecDeque<usize> = neighbours.into_iter().map(|n| n.1).collect();
+            while let Some(jj) = queue.pop_front() {
+                if labels[jj].is_none() {
+                    labels[jj] = Some(cluster);
+                }
+                if visited[jj] {
+                    continue;
+                }
+                visited[jj] = true;
+
+                let neighbours = neighbourhoods.get(jj, self.epsilon);
+                if neighbours.len() >= self.min_points {
+                    queue.extend(neighbours.into_iter().map(|n| n.1));
+                }
+            }
+
+            cluster += 1;
+        }
+
+        Ok(Clustering::from_labels(&labels, digests, self.with_len))
+    }
+}
+
+/// Cluster ordering (OPTICS), following M. Ankerst, M. M. Breunig, H.-P. Kriegel and J. Sander
+/// (1999), "OPTICS: Ordering Points To Identify the Clustering Structure".
+///
+/// Unlike [`Dbscan`], OPTICS does not compute a clustering for a fixed ```epsilon```, but an
+/// ordering of the digests with reachability distances (see [`OpticsOrdering`]), from which
+/// clusterings for all ```epsilon``` up to ```max_epsilon``` can be extracted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Optics {
+    max_epsilon: usize,
+    min_points: usize,
+    with_len: bool,
+    use_index: bool,
+}
+
+impl Optics {
+    /// Constructs a cluster ordering with the given parameters. ```with_len``` determines whether
+    /// the length difference is considered in all distances (see [`Tlsh::diff`]).
+    pub fn new(max_epsilon: usize, min_points: usize, with_len: bool) -> Self {
+        Self {
+            max_epsilon,
+            min_points,
+            with_len,
+            use_index: true,
+        }
+    }
+
+    /// Sets whether neighbours are found with a [`VpTree`] or by comparing all pairs of digests.
+    /// Both give the same results. Default: ```true```.
+    pub fn with_index(mut self, use_index: bool) -> Self {
+        self.use_index = use_index;
+        self
+    }
+
+    /// Computes the cluster ordering of the given digests. Ties between equally reachable digests
+    /// are broken by their position, so results are deterministic.
+    ///
+    /// Returns an error if the digests have different [`BucketKind`](crate::BucketKind)s.
+    pub fn order(&self, digests: &[Tlsh]) -> Result<OpticsOrdering, TlshError> {
+        let neighbourhoods = Neighbourhoods::new(digests, self.with_len, self.use_index)?;
+        let mut order = Vec::with_capacity(digests.len());
+        let mut reachability: Vec<Option<usize>> = vec![None; digests.len()];
+        let mut core_distances: Vec<Option<usize>> = vec![None; digests.len()];
+        let mut processed = vec![false; digests.len()];
+
+        for start in 0..digests.len() {
+            if processed[start] {
+                continue;
+            }
+
+            // Min-heap of (reachability, position). Outdated entries are skipped when popped.
+            let mut seeds = BinaryHeap::new();
+            seeds.push(Reverse((usize::MAX, start)));
+
+            while let Some(Reverse((_, ii))) = seeds.pop() {
+                if processed[ii] {
+                    continue;
+                }
+                processed[ii] = true;
+                order.push(ii);
+
+                let neighbours = neighbourhoods.get(ii, self.max_epsilon);
+                let core_distance = core_distance(&neighbours, self.min_points);
+                core_distances[ii] = core_distance;
+
+                if let Some(core_distance) = core_distance {
+                    for &(distance, jj) in &neighbours {
+                        if processed[jj] {
+                            continue;
+                        }
+
+                        let reach = core_distance.max(distance);
+                        if reachability[jj].map_or(true, |r| reach < r) {
+                            reachability[jj] = Some(reach);
+                            seeds.push(Reverse((reach, jj)));
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(OpticsOrdering {
+            order,
+            reachability,
+            core_distances,
+            with_len: self.with_len,
+        })
+    }
+}
+
+/// The result of [`Optics::order`].
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct OpticsOrdering {
+    order: Vec<usize>,
+    reachability: Vec<Option<usize>>,
+    core_distances: Vec<Option<usize>>,
+    with_len: bool,
+}
+
+impl OpticsOrdering {
+    /// Returns the positions of the digests in cluster order.
+    pub fn order(&self) -> &[usize] {
+        &self.order
+    }
+
+    /// Returns the reachability distance of each digest, indexed by position, or ```None``` if the
+    /// digest is not reachable within ```max_epsilon``` from any digest processed before it.
+    pub fn reachability(&self) -> &[Option<usize>] {
+        &self.reachability
+    }
+
+    /// Returns the core distance of each digest, indexed by position, i.e. the distance to its
+    /// ```min_points```-th nearest digest (including itself), or ```None``` if the digest has fewer
+    /// than ```min_points``` neighbours within ```max_epsilon```.
+    pub fn core_distances(&self) -> &[Option<usize>] {
+        &self.core_distances
+    }
+
+    /// Returns pairs of position and reachability distance in cluster order, i.e. the data of a
+    /// reachability plot.
+    pub fn reachability_plot(&self) -> Vec<(usize, Option<usize>)> {
+        self.order
+            .iter()
+            .map(|&ii| (ii, self.reachability[ii]))
+            .collect()
+    }
+
+    /// Extracts a DBSCAN-like clustering for the given ```epsilon```, which must not exceed the
+    /// ```max_epsilon``` of the ordering. ```digests``` must be the digests the ordering was
+    /// computed from; they are used to choose representatives.
+    ///
+    /// Core digests are clustered exactly like by [`Dbscan`] with the same parameters, while a
+    /// few border digests may be labelled as noise instead.
+    pub fn extract(&self, digests: &[Tlsh], epsilon: usize) -> Clustering {
+        let mut labels: Vec<Option<usize>> = vec![None; self.order.len()];
+        let mut cluster: Option<usize> = None;
+        let mut next = 0;
+
+        for &ii in &self.order {
+            if self.reachability[ii].map_or(true, |r| r > epsilon) {
+                cluster = if self.core_distances[ii].is_some_and(|c| c <= epsilon) {
+                    next += 1;
+                    Some(next - 1)
+                } else {
+                    None
+                };
+            }
+            labels[ii] = cluster;
+        }
+
+        Clustering::from_labels(&labels, digests, self.with_len)
+    }
+}
+
+/// Finds all digests within a distance of a digest, either with a [`VpTree`] or by comparing it
+/// with all digests.
+struct Neighbourhoods<'a> {
+    digests: &'a [Tlsh],
+    with_len: bool,
+    tree: Option<VpTree<usize>>,
+}
+
+impl<'a> Neighbourhoods<'a> {
+    fn new(digests: &'a [Tlsh], with_len: bool, use_index: bool) -> Result<Self, TlshError> {
+        let tree = if use_index {
+            Some(VpTree::new(
+                digests.iter().cloned().enumerate().map(|(ii, t)| (t, ii)),
+                with_len,
+            )?)
+        } else {
+            check_bucket_kinds(digests)?;
+            None
+        };
+
+        Ok(Self {
+            digests,
+            with_len,
+            tree,
+        })
+    }
+
+    /// Returns pairs of distance and position of all digests within ```epsilon``` of the digest
+    /// at position ```idx```, including itself, ordered by distance and then by position.
+    fn get(&self, idx: usize, epsilon: usize) -> Vec<(usize, usize)> {
+        let query = &self.digests[idx];
+        match &self.tree {
+            Some(tree) => tree
+                .range(query, epsilon)
+                .expect("Digests have been checked to have the same bucket kind")
+                .into_iter()
+                .map(|n| (n.distance, *n.value))
+                .collect(),
+            None => {
+                let mut result: Vec<(usize, usize)> = self
+                    .digests
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(ii, t)| {
+                        query
+                            .diff_within(t, self.with_len, epsilon)
+                            .map(|d| (d, ii))
+                    })
+                    .collect();
+                result.sort_unstable();
+                result
+            }
+        }
+    }
+}
+
+/// Returns the distance to the ```min_points```-th nearest neighbour, given all neighbours
+/// ordered by distance.
+fn core_distance(neighbours: &[(usize, usize)], min_points: usize) -> Option<usize> {
+    match min_points {
+        0 => Some(0),
+        n => neighbours.get(n - 1).map(|x| x.0),
+    }
+}
+
+/// Returns the distance and value of the nearest neighbour of ```query``` other than ```id```, if
+/// its distance does not exceed ```cutoff```.
+fn nearest_other(
+    tree: &VpTree<usize>,
+    query: &Tlsh,
+    id: usize,
+    cutoff: usize,
+) -> Result<Option<(usize, usize)>, TlshError> {
+    Ok(tree
+        .nearest(query, 2)?
+        .into_iter()
+        .find(|n| *n.value != id)
+        .filter(|n| n.distance <= cutoff)
+        .map(|n| (n.distance, *n.value)))
+}
+
+/// Returns the member with the smallest sum of distances to all members, considering at most
+/// [`MEDOID_CANDIDATES`] evenly spaced members as candidates.
+pub(crate) fn medoid(members: &[usize], digests: &[Tlsh], with_len: bool) -> usize {
+    let step = members.len().div_ceil(MEDOID_CANDIDATES).max(1);
+    members
+        .iter()
+        .step_by(step)
+        .map(|&c| {
+            let sum: usize = members
+                .iter()
+                .map(|&m| digests[c].diff(&digests[m], with_len))
+                .sum();
+            (sum, c)
+        })
+        .min()
+        .map_or(0, |(_, c)| c)
+}
+
+/// Clusters of [`HacT`], each identified by the position of its centre. Every member lies within
+/// the cutoff of the centre of its cluster.
+#[derive(Clone, Debug)]
+struct CentredClusters<'a> {
+    digests: &'a [Tlsh],
+    cutoff: usize,
+    with_len: bool,
+    centres: Vec<usize>,
+    // Members of the cluster centred at each position, empty for positions which are no centre.
+    members: Vec<Vec<usize>>,
+}
+
+impl<'a> CentredClusters<'a> {
+    /// Starts with one cluster per digest.
+    fn new(digests: &'a [Tlsh], cutoff: usize, with_len: bool) -> Self {
+        Self {
+            digests,
+            cutoff,
+            with_len,
+            centres: (0..digests.len()).collect(),
+            members: (0..digests.len()).map(|ii| vec![ii]).collect(),
+        }
+    }
+
+    /// Returns the positions of all centres in ascending order.
+    fn centres(&self) -> Vec<usize> {
+        (0..self.members.len())
+            .filter(|&c| !self.members[c].is_empty())
+            .collect()
+    }
+
+    /// Processes links between digests in ascending order of distance and merges their clusters
+    /// where possible. Returns ```true``` if any clusters were merged.
+    fn merge(&mut self, mut links: Vec<(usize, usize, usize)>) -> bool {
+        links.sort_unstable();
+
+        let mut merged = false;
+        for (_, x, y) in links {
+            let (cx, cy) = (self.centres[x], self.centres[y]);
+            if cx != cy && self.try_merge(cx, cy) {
+                merged = true;
+            }
+        }
+        merged
+    }
+
+    /// Merges two clusters if all members of one lie within the cutoff of the centre of the other.
+    /// The centre of the larger cluster is tried first.
+    fn try_merge(&mut self, x: usize, y: usize) -> bool {
+        let (x, y) = if (self.members[y].len(), x) > (self.members[x].len(), y) {
+            (y, x)
+        } else {
+            (x, y)
+        };
+
+        for &(centre, other) in &[(x, y), (y, x)] {
+            let within = self.members[other].iter().all(|&m| {
+                self.digests[centre]
+                    .diff_within(&self.digests[m], self.with_len, self.cutoff)
+                    .is_some()
+            });
+
+            if within {
+                let moved = std::mem::take(&mut self.members[other]);
+                for &m in &moved {
+                    self.centres[m] = centre;
+                }
+                self.members[centre].extend(moved);
+                return true;
+            }
+        }
+
+        false
+    }
+
+    fn into_clustering(self) -> Clustering {
+        let labels: Vec<Option<usize>> = self.centres.iter().map(|&c| Some(c)).collect();
+        let centres = self.centres;
+        Clustering::with_representatives(&labels, |members| centres[members[0]])
+    }
+}
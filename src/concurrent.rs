@@ -0,0 +1,233 @@
+use std::{
+    collections::HashMap,
+    io,
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        RwLock, RwLockReadGuard, RwLockWriteGuard,
+    },
+};
+
+use crate::{
+    disk_index::replace_index, helper::check_digest, BucketKind, ChecksumKind, DiskIndex,
+    IndexMetadata, Tlsh, TlshError,
+};
+
+const DEFAULT_SHARD_COUNT: usize = 16;
+
+type Shard<T> = HashMap<u64, (Tlsh, T)>;
+
+/// A digest found by a query against a [`ConcurrentIndex`], owning a copy of its payload.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct OwnedNeighbour<T> {
+    /// Id returned by [`ConcurrentIndex::insert`].
+    pub id: u64,
+    /// Distance to the query as computed by [`Tlsh::diff`].
+    pub distance: usize,
+    /// The digest found.
+    pub tlsh: Tlsh,
+    /// The payload attached to the digest.
+    pub value: T,
+}
+
+/// An index of digests with payloads which can be queried and modified concurrently through a
+/// shared reference.
+///
+/// Entries are distributed over shards by their id, and each shard is protected by its own
+/// [`RwLock`]. Inserts and removals only lock a single shard for writing, so they block queries
+/// only briefly. A query visits the shards one after another, so it may or may not observe
+/// modifications made while it runs. A snapshot locks all shards at once and therefore observes
+/// a consistent state of the index.
+///
+/// Shards are plain hash maps, so a query compares the query digest with every entry, using
+/// [`Tlsh::diff_within`] to stop early. For large collections which rarely change, a
+/// [`VpTree`](crate::VpTree) or [`DigestSet`](crate::DigestSet) built from a snapshot answers
+/// queries faster.
+#[derive(Debug)]
+pub struct ConcurrentIndex<T> {
+    bucket_kind: BucketKind,
+    checksum_kind: ChecksumKind,
+    with_len: bool,
+    next_id: AtomicU64,
+    shards: Vec<RwLock<Shard<T>>>,
+}
+
+impl<T> ConcurrentIndex<T> {
+    /// Constructs an empty index for digests of the given configuration. ```with_len```
+    /// determines whether the length difference is considered in queries (see [`Tlsh::diff`]).
+    pub fn new(bucket_kind: BucketKind, checksum_kind: ChecksumKind, with_len: bool) -> Self {
+        Self {
+            bucket_kind,
+            checksum_kind,
+            with_len,
+            next_id: AtomicU64::new(0),
+            shards: (0..DEFAULT_SHARD_COUNT)
+                .map(|_| RwLock::new(HashMap::new()))
+                .collect(),
+        }
+    }
+
+    /// Sets the number of shards. More shards reduce contention between concurrent writers.
+    /// Default: 16.
+    ///
+    /// # Panics
+    /// Panics if ```count``` is zero or the index is not empty.
+    pub fn with_shard_count(mut self, count: usize) -> Self {
+        assert!(count > 0, "Shard count must be positive");
+        assert!(self.is_empty(), "Shard count must be set on an empty index");
+        self.shards = (0..count).map(|_| RwLock::new(HashMap::new())).collect();
+        self
+    }
+
+    /// Returns the number of entries in the index.
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(|s| read(s).len()).sum()
+    }
+
+    /// Returns ```true``` if the index contains no entries.
+    pub fn is_empty(&self) -> bool {
+        self.shards.iter().all(|s| read(s).is_empty())
+    }
+
+    /// Adds a digest with its payload to the index and returns its id. Ids are unique and never
+    /// reused.
+    ///
+    /// Returns an error if the digest has a different configuration than the index.
+    pub fn insert(&self, tlsh: Tlsh, value: T) -> Result<u64, TlshError> {
+        check_digest(&tlsh, self.bucket_kind, self.checksum_kind)?;
+
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        write(self.shard(id)).insert(id, (tlsh, value));
+        Ok(id)
+    }
+
+    /// Removes the entry with the given id and returns its digest and payload, or ```None``` if
+    /// there is no such entry.
+    pub fn remove(&self, id: u64) -> Option<(Tlsh, T)> {
+        write(self.shard(id)).remove(&id)
+    }
+
+    /// Returns ```true``` if the index contains an entry with the given id.
+    pub fn contains(&self, id: u64) -> bool {
+        read(self.shard(id)).contains_key(&id)
+    }
+
+    /// Loads an index from a snapshot written by [`ConcurrentIndex::write_snapshot`].
+    /// ```value``` converts the metadata of each entry back to its payload.
+    ///
+    /// The ids of the loaded index equal the ids of the entries in the file, i.e. the positions in
+    /// the id mapping returned by [`ConcurrentIndex::write_snapshot`].
+    pub fn load_snapshot<P, F>(path: P, with_len: bool, mut value: F) -> io::Result<Self>
+    where
+        P: AsRef<Path>,
+        F: FnMut(IndexMetadata) -> T,
+    {
+        let mut disk = DiskIndex::open_read_only(path)?;
+        let result = Self::new(disk.bucket_kind(), disk.checksum_kind(), with_len);
+        for id in 0..disk.len() {
+            let tlsh = disk.digest(id)?;
+            result.insert(tlsh, value(disk.metadata(id)?))?;
+        }
+        Ok(result)
+    }
+
+    fn shard(&self, id: u64) -> &RwLock<Shard<T>> {
+        &self.shards[(id % self.shards.len() as u64) as usize]
+    }
+}
+
+impl<T: Clone> ConcurrentIndex<T> {
+    /// Returns a copy of the digest and payload of the entry with the given id.
+    pub fn get(&self, id: u64) -> Option<(Tlsh, T)> {
+        read(self.shard(id)).get(&id).cloned()
+    }
+
+    /// Returns all entries whose distance to the query does not exceed ```threshold```, ordered
+    /// by distance and then by id.
+    ///
+    /// Returns an error if the query has a different [`BucketKind`] than the index.
+    pub fn query(
+        &self,
+        query: &Tlsh,
+        threshold: usize,
+    ) -> Result<Vec<OwnedNeighbour<T>>, TlshError> {
+        if query.bucket_kind() != self.bucket_kind {
+            Err(TlshError::BucketKindMismatch {
+                left: query.bucket_kind(),
+                right: self.bucket_kind,
+            })?
+        }
+
+        let mut result = Vec::new();
+        for shard in &self.shards {
+            for (&id, (tlsh, value)) in read(shard).iter() {
+                if let Some(distance) = query.diff_within(tlsh, self.with_len, threshold) {
+                    result.push(OwnedNeighbour {
+                        id,
+                        distance,
+                        tlsh: tlsh.clone(),
+                        value: value.clone(),
+                    });
+                }
+            }
+        }
+
+        result.sort_unstable_by_key(|n| (n.distance, n.id));
+        Ok(result)
+    }
+
+    /// Returns a consistent copy of all entries, ordered by id. All shards are locked for reading
+    /// at the same time, so no modification is observed partially.
+    pub fn snapshot(&self) -> Vec<(u64, Tlsh, T)> {
+        let guards: Vec<RwLockReadGuard<'_, Shard<T>>> = self.shards.iter().map(read).collect();
+
+        let mut result: Vec<(u64, Tlsh, T)> = guards
+            .iter()
+            .flat_map(|g| g.iter())
+            .map(|(&id, (tlsh, value))| (id, tlsh.clone(), value.clone()))
+            .collect();
+        result.sort_unstable_by_key(|e| e.0);
+        result
+    }
+
+    /// Writes a snapshot of the index to a [`DiskIndex`](crate::DiskIndex) file. ```metadata```
+    /// converts each payload to the metadata stored in the file.
+    ///
+    /// The file is written to a temporary file next to ```path``` first and then renamed over
+    /// ```path```, so readers never observe a partially written snapshot. The file numbers its
+    /// entries by itself (see [`DiskIndex::compact`]), so the returned vector maps each id in the
+    /// file to the id of the entry in this index.
+    pub fn write_snapshot<P, F>(&self, path: P, mut metadata: F) -> io::Result<Vec<u64>>
+    where
+        P: AsRef<Path>,
+        F: FnMut(&T) -> IndexMetadata,
+    {
+        let snapshot = self.snapshot();
+        let mut entries: Vec<(Tlsh, usize)> = snapshot
+            .iter()
+            .enumerate()
+            .map(|(ii, (_, tlsh, _))| (tlsh.clone(), ii))
+            .collect();
+
+        replace_index(
+            path.as_ref(),
+            self.bucket_kind,
+            self.checksum_kind,
+            &mut entries,
+            |ii| Ok(metadata(&snapshot[ii].2)),
+        )?;
+
+        // Entries were sorted in place into the order of the file.
+        Ok(entries.iter().map(|&(_, ii)| snapshot[ii].0).collect())
+    }
+}
+
+// A panic while holding a lock cannot leave a shard in an inconsistent state, since all
+// modifications are single map operations. Poisoning is therefore ignored.
+fn read<T>(lock: &RwLock<T>) -> RwLockReadGuard<'_, T> {
+    lock.read().unwrap_or_else(|e| e.into_inner())
+}
+
+fn write<T>(lock: &RwLock<T>) -> RwLockWriteGuard<'_, T> {
+    lock.write().unwrap_or_else(|e| e.into_inner())
+}
@@ -0,0 +1,191 @@
+use crate::{
+    distance::{CHECKSUM_PENALTY, LEN_WEIGHT, OPPOSITE_CODE_DISTANCE, QRATIO_WEIGHT},
+    helper::{check_digest, len_distance, qratio_distance},
+    BucketKind, ChecksumKind, Tlsh, TlshError,
+};
+
+/// Mask selecting the low bit of each 2-bit code in a word.
+const LOW_BITS: u64 = 0x5555_5555_5555_5555;
+
+/// A compact collection of digests of the same configuration, optimised for comparing one query
+/// against all digests.
+///
+/// Unlike a ```Vec<Tlsh>```, which stores the checksum and body of each digest in separate heap
+/// allocations, a set stores all header values and all bodies in contiguous arrays. Bodies are
+/// packed into 64-bit words, so that [`DigestSet::scan`] compares 32 codes per step with a few
+/// bitwise operations. Header distances are looked up in tables computed once per query, and
+/// bodies are only compared if the header distance does not exceed the threshold.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct DigestSet {
+    bucket_kind: BucketKind,
+    checksum_kind: ChecksumKind,
+    words: usize,
+    lvalues: Vec<u8>,
+    qratios: Vec<u8>,
+    checksums: Vec<u8>,
+    codes: Vec<u64>,
+}
+
+impl DigestSet {
+    /// Constructs an empty set for digests of the given configuration.
+    pub fn new(bucket_kind: BucketKind, checksum_kind: ChecksumKind) -> Self {
+        Self {
+            bucket_kind,
+            checksum_kind,
+            words: (bucket_kind.bucket_count() / 4).div_ceil(8),
+            lvalues: Vec::new(),
+            qratios: Vec::new(),
+            checksums: Vec::new(),
+            codes: Vec::new(),
+        }
+    }
+
+    /// Constructs a set from a collection of digests. Returns an error if a digest has a
+    /// different configuration than given.
+    pub fn from_digests<'a, I>(
+        bucket_kind: BucketKind,
+        checksum_kind: ChecksumKind,
+        digests: I,
+    ) -> Result<Self, TlshError>
+    where
+        I: IntoIterator<Item = &'a Tlsh>,
+    {
+        let mut result = Self::new(bucket_kind, checksum_kind);
+        for tlsh in digests {
+            result.push(tlsh)?;
+        }
+        Ok(result)
+    }
+
+    /// Returns the number of digests in the set.
+    pub fn len(&self) -> usize {
+        self.lvalues.len()
+    }
+
+    /// Returns ```true``` if the set contains no digests.
+    pub fn is_empty(&self) -> bool {
+        self.lvalues.is_empty()
+    }
+
+    /// Appends a digest to the set and returns its position.
+    ///
+    /// Returns an error if the digest has a different configuration than the set.
+    pub fn push(&mut self, tlsh: &Tlsh) -> Result<usize, TlshError> {
+        check_digest(tlsh, self.bucket_kind, self.checksum_kind)?;
+
+        self.lvalues.push(tlsh.lvalue());
+        self.qratios.push(tlsh.q1ratio() << 4 | tlsh.q2ratio());
+        self.checksums.extend_from_slice(tlsh.checksum());
+        self.codes.extend(pack_codes(tlsh.codes(), self.words));
+
+        Ok(self.len() - 1)
+    }
+
+    /// Compares the query against all digests of the set and returns pairs of position and
+    /// distance for all digests whose distance does not exceed ```threshold```, ordered by
+    /// position. Distances are equal to those computed by [`Tlsh::diff`].
+    ///
+    /// Returns an error if the query has a different [`BucketKind`] than the set.
+    pub fn scan(
+        &self,
+        query: &Tlsh,
+        threshold: usize,
+        with_len: bool,
+    ) -> Result<Vec<(usize, usize)>, TlshError> {
+        if query.bucket_kind() != self.bucket_kind {
+            Err(TlshError::BucketKindMismatch {
+                left: query.bucket_kind(),
+                right: self.bucket_kind,
+            })?
+        }
+
+        let query_codes = pack_codes(query.codes(), self.words);
+        let query_checksum = query.checksum();
+        let checksum_len = self.checksum_kind.checksum_len();
+
+        // Header distances only depend on a few values, so they are looked up instead of computed.
+        let mut qratio_table = [0usize; 256];
+        for (qratio, d) in qratio_table.iter_mut().enumerate() {
+            *d = qratio_distance(query.q1ratio() as usize, qratio >> 4, QRATIO_WEIGHT)
+                + qratio_distance(query.q2ratio() as usize, qratio & 0xF, QRATIO_WEIGHT);
+        }
+        let mut len_table = [0usize; 256];
+        if with_len {
+            for (lvalue, d) in len_table.iter_mut().enumerate() {
+                *d = len_distance(query.lvalue() as usize, lvalue, LEN_WEIGHT);
+            }
+        }
+
+        let mut result = Vec::new();
+        for ii in 0..self.len() {
+            let mut distance =
+                qratio_table[self.qratios[ii] as usize] + len_table[self.lvalues[ii] as usize];
+
+            // Zipping compares only the first byte if the checksum kinds differ.
+            let checksum = &self.checksums[ii * checksum_len..(ii + 1) * checksum_len];
+            if query_checksum.iter().zip(checksum).any(|(x, y)| x != y) {
+                distance += CHECKSUM_PENALTY;
+            }
+
+            if distance > threshold {
+                continue;
+            }
+
+            let codes = &self.codes[ii * self.words..(ii + 1) * self.words];
+            for (&x, &y) in codes.iter().zip(&query_codes) {
+                distance += word_distance(x, y);
+            }
+
+            if distance <= threshold {
+                result.push((ii, distance));
+            }
+        }
+
+        Ok(result)
+    }
+}
+
+/// Packs a body into ```words``` 64-bit words. Unused codes of the last word are zero, so they do
+/// not contribute to any distance.
+fn pack_codes(codes: &[u8], words: usize) -> Vec<u64> {
+    let mut result = vec![0u64; words];
+    for (ii, &c) in codes.iter().enumerate() {
+        result[ii / 8] |= (c as u64) << ((ii % 8) * 8);
+    }
+    result
+}
+
+/// Computes the body distance of 32 pairs of 2-bit codes at once.
+///
+/// Two codes have distance 0, 1 or 2 if their absolute difference is 0, 1 or 2, and distance
+/// [`OPPOSITE_CODE_DISTANCE`] if they are 0 and 3. For each pair, let ```h``` and ```l``` state
+/// whether the high and low bits differ. Then the absolute difference is 0 if neither differs, 2 if
+/// only ```h``` is set, and 1 if only ```l``` is set. If both are set, the difference is 3 if the
+/// high and low bits of ```x``` are equal and 1 otherwise.
+///
+/// The absolute differences are summed up like in a population count, with the opposite pairs
+/// counted a second time with weight ```OPPOSITE_CODE_DISTANCE - 3```. This avoids relying on a
+/// hardware population count instruction, which is not part of the baseline of most targets.
+fn word_distance(x: u64, y: u64) -> usize {
+    const PAIRS: u64 = 0x3333_3333_3333_3333;
+    const NIBBLES: u64 = 0x0F0F_0F0F_0F0F_0F0F;
+    const BYTES: u64 = 0x0101_0101_0101_0101;
+
+    let xor = x ^ y;
+    let high = (xor >> 1) & LOW_BITS;
+    let low = xor & LOW_BITS;
+    let uniform = !((x >> 1) ^ x) & LOW_BITS;
+
+    // Absolute difference of each pair of codes, and a flag for opposite codes.
+    let opposite = high & low & uniform;
+    let abs = ((high & !(low & !uniform)) << 1) | low;
+
+    // Nibble sums are at most 2 * 3 + 2 * 3 = 12 and byte sums at most 24, so
+    // nothing overflows, not even the total of at most 192.
+    let abs = (abs & PAIRS) + ((abs >> 2) & PAIRS);
+    let opposite = (opposite & PAIRS) + ((opposite >> 2) & PAIRS);
+    let sum = abs + (OPPOSITE_CODE_DISTANCE as u64 - 3) * opposite;
+    let sum = (sum & NIBBLES) + ((sum >> 4) & NIBBLES);
+
+    (sum.wrapping_mul(BYTES) >> 56) as usize
+}
@@ -0,0 +1,664 @@
+use std::{
+    convert::TryFrom,
+    fs::{self, File, OpenOptions},
+    io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::{
+    distance::{LEN_WEIGHT, QRATIO_WEIGHT},
+    helper::{check_digest, invalid_data, len_distance, qratio_distance, read_u32, read_u64},
+    tlsh::{binary_len, check_config},
+    BucketKind, ChecksumKind, Tlsh, TlshError,
+};
+
+const MAGIC: &[u8; 8] = b"TLSHIDX\0";
+const FORMAT_VERSION: u32 = 1;
+const HEADER_LEN: u64 = 40;
+const PARTITION_LEN: u64 = 20;
+const META_OFFSET_LEN: usize = 8;
+
+/// User metadata stored along with each digest of a [`DiskIndex`].
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct IndexMetadata {
+    /// Path of the hashed file.
+    pub path: String,
+    /// Exact size of the hashed file in bytes.
+    pub size: u64,
+    /// Free-form label, e.g. a malware family.
+    pub label: String,
+}
+
+/// A digest found by a query against a [`DiskIndex`].
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct IndexHit {
+    /// Id of the entry, which can be used to load its metadata with [`DiskIndex::metadata`].
+    pub id: usize,
+    /// Distance to the query as computed by [`Tlsh::diff`].
+    pub distance: usize,
+    /// The digest found.
+    pub tlsh: Tlsh,
+}
+
+/// A range of records sharing the same L-value, q1 ratio and q2 ratio.
+#[derive(Clone, Copy, Debug)]
+struct Partition {
+    lvalue: u8,
+    q1ratio: u8,
+    q2ratio: u8,
+    start: u64,
+    count: u64,
+}
+
+/// A persistent index of digests with metadata, stored in a single file.
+///
+/// Opening an index only loads its header and partition table. A query reads only the records of
+/// partitions whose header lower bound (see [`Tlsh::header_lower_bound`]) does not exceed the
+/// threshold, and metadata is only loaded on request. New entries are appended to the end of the
+/// file and are kept in memory until the index is compacted.
+///
+/// # File format
+/// All integers are stored in little endian. The file consists of the following sections:
+/// - Header (40 bytes): magic ```b"TLSHIDX\0"```, format version (u32), configuration byte with the
+///   bucket kind in bits 0-1 and the checksum kind in bit 2 (see [`Tlsh::to_bytes`]), three
+///   reserved bytes, and the number of records, partitions and metadata bytes (u64 each).
+/// - Records: the compact binary form of each digest followed by the absolute file offset of its
+///   metadata (u64), sorted by L-value, q1 ratio and q2 ratio.
+/// - Partitions (20 bytes each): L-value, q1 ratio and q2 ratio (u8 each), a reserved byte, and
+///   index of the first record and number of records (u64 each).
+/// - Metadata: for each record its size (u64), path and label (u32 length followed by UTF-8 bytes
+///   each).
+/// - Appended entries until the end of the file: the compact binary form of the digest directly
+///   followed by its metadata.
+///
+/// Ids of entries are stable until [`DiskIndex::compact`] is called.
+///
+/// If a process is interrupted while appending, the last appended entry may be incomplete. Such
+/// an entry is dropped and the file is truncated to the last complete entry when the index is
+/// opened again.
+#[derive(Debug)]
+pub struct DiskIndex {
+    path: PathBuf,
+    file: File,
+    read_only: bool,
+    bucket_kind: BucketKind,
+    checksum_kind: ChecksumKind,
+    record_count: u64,
+    partitions: Vec<Partition>,
+    appended: Vec<(Tlsh, u64)>,
+}
+
+impl DiskIndex {
+    /// Creates an empty index file for digests of the given configuration. An existing file is
+    /// truncated.
+    ///
+    /// Returns an error with kind [`io::ErrorKind::InvalidInput`] if the configuration has no
+    /// digest format, see [`TlshError::UnsupportedConfiguration`].
+    pub fn create<P: AsRef<Path>>(
+        path: P,
+        bucket_kind: BucketKind,
+        checksum_kind: ChecksumKind,
+    ) -> io::Result<Self> {
+        check_config(bucket_kind, checksum_kind)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        write_index(
+            path.as_ref(),
+            bucket_kind,
+            checksum_kind,
+            &mut [],
+            |_| unreachable!(),
+        )?;
+        Self::open(path)
+    }
+
+    /// Opens an existing index file for reading and appending. An incomplete last entry, e.g.
+    /// after a crash during [`DiskIndex::append`], is removed from the file.
+    ///
+    /// Returns an error with kind [`io::ErrorKind::InvalidData`] if the file is not a valid index.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::open_with(path.as_ref(), false)
+    }
+
+    /// Opens an existing index file for queries only, e.g. on a read-only file system. The file
+    /// is never modified: an incomplete last entry is skipped instead of being removed, and
+    /// [`DiskIndex::append`], [`DiskIndex::extend`] and [`DiskIndex::compact`] return an error
+    /// with kind [`io::ErrorKind::PermissionDenied`].
+    ///
+    /// Returns an error with kind [`io::ErrorKind::InvalidData`] if the file is not a valid index.
+    pub fn open_read_only<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::open_with(path.as_ref(), true)
+    }
+
+    fn open_with(path: &Path, read_only: bool) -> io::Result<Self> {
+        let path = path.to_path_buf();
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(!read_only)
+            .open(&path)?;
+        let file_len = file.metadata()?.len();
+
+        let mut header = [0u8; HEADER_LEN as usize];
+        file.read_exact(&mut header)
+            .map_err(|_| invalid_data("Truncated index header"))?;
+
+        if &header[..8] != MAGIC {
+            Err(invalid_data("Not a TLSH index file"))?
+        }
+        if read_u32(&header[8..12]) != FORMAT_VERSION {
+            Err(invalid_data("Unsupported index format version"))?
+        }
+        let (bucket_kind, checksum_kind) = decode_config(header[12])?;
+        let record_count = read_u64(&header[16..24]);
+        let partition_count = read_u64(&header[24..32]);
+        let metadata_len = read_u64(&header[32..40]);
+
+        let record_len = (binary_len(bucket_kind, checksum_kind) + META_OFFSET_LEN) as u64;
+        let partitions_offset = record_count
+            .checked_mul(record_len)
+            .and_then(|x| x.checked_add(HEADER_LEN))
+            .ok_or_else(|| invalid_data("Invalid record count"))?;
+        let appended_offset = partition_count
+            .checked_mul(PARTITION_LEN)
+            .and_then(|x| x.checked_add(partitions_offset))
+            .and_then(|x| x.checked_add(metadata_len))
+            .filter(|&x| x <= file_len)
+            .ok_or_else(|| invalid_data("Truncated index file"))?;
+
+        let mut reader = BufReader::new(&mut file);
+        reader.seek(SeekFrom::Start(partitions_offset))?;
+
+        let mut partitions = Vec::with_capacity(partition_count as usize);
+        let mut next = 0;
+        for _ in 0..partition_count {
+            let mut buf = [0u8; PARTITION_LEN as usize];
+            reader.read_exact(&mut buf)?;
+            let partition = Partition {
+                lvalue: buf[0],
+                q1ratio: buf[1],
+                q2ratio: buf[2],
+                start: read_u64(&buf[4..12]),
+                count: read_u64(&buf[12..20]),
+            };
+
+            if partition.start != next || partition.q1ratio > 15 || partition.q2ratio > 15 {
+                Err(invalid_data("Invalid partition table"))?
+            }
+            next += partition.count;
+            partitions.push(partition);
+        }
+        if next != record_count {
+            Err(invalid_data("Invalid partition table"))?
+        }
+
+        let mut appended = Vec::new();
+        reader.seek(SeekFrom::Start(appended_offset))?;
+        let mut offset = appended_offset;
+        while offset < file_len {
+            let entry = read_digest(&mut reader, bucket_kind, checksum_kind).and_then(|tlsh| {
+                let meta_offset = reader.stream_position()?;
+                read_metadata(&mut reader)?;
+                Ok((tlsh, meta_offset))
+            });
+
+            match entry {
+                Ok(entry) => appended.push(entry),
+                // Only the last entry can be incomplete, since entries are only ever appended.
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(e) => Err(e)?,
+            }
+            offset = reader.stream_position()?;
+        }
+
+        drop(reader);
+        if offset < file_len && !read_only {
+            file.set_len(offset)?;
+        }
+
+        Ok(Self {
+            path,
+            file,
+            read_only,
+            bucket_kind,
+            checksum_kind,
+            record_count,
+            partitions,
+            appended,
+        })
+    }
+
+    /// Returns the number of entries in the index.
+    pub fn len(&self) -> usize {
+        self.record_count as usize + self.appended.len()
+    }
+
+    /// Returns ```true``` if the index contains no entries.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the number of buckets of the digests in the index.
+    pub fn bucket_kind(&self) -> BucketKind {
+        self.bucket_kind
+    }
+
+    /// Returns the checksum length of the digests in the index.
+    pub fn checksum_kind(&self) -> ChecksumKind {
+        self.checksum_kind
+    }
+
+    /// Returns the number of entries appended since the index was last compacted.
+    pub fn appended_len(&self) -> usize {
+        self.appended.len()
+    }
+
+    /// Appends an entry to the end of the index file and returns its id. If writing fails, the
+    /// file is truncated to its previous length.
+    ///
+    /// Returns an error with kind [`io::ErrorKind::InvalidData`] if the digest has a different
+    /// configuration than the index.
+    pub fn append(&mut self, tlsh: Tlsh, metadata: &IndexMetadata) -> io::Result<usize> {
+        self.check_writable()?;
+        check_digest(&tlsh, self.bucket_kind, self.checksum_kind)?;
+
+        let offset = self.file.seek(SeekFrom::End(0))?;
+        let mut buf = tlsh.to_bytes();
+        let meta_offset = offset + buf.len() as u64;
+        write_metadata(&mut buf, metadata)?;
+        if let Err(e) = self.file.write_all(&buf) {
+            // Otherwise all later entries would be misaligned.
+            self.file.set_len(offset)?;
+            Err(e)?
+        }
+
+        self.appended.push((tlsh, meta_offset));
+        Ok(self.len() - 1)
+    }
+
+    /// Appends multiple entries to the end of the index file. Either all entries are appended or,
+    /// if an error occurs, none of them.
+    pub fn extend<I>(&mut self, entries: I) -> io::Result<()>
+    where
+        I: IntoIterator<Item = (Tlsh, IndexMetadata)>,
+    {
+        self.check_writable()?;
+
+        let start = self.file.seek(SeekFrom::End(0))?;
+        let appended_len = self.appended.len();
+        let result = self.write_entries(start, entries);
+
+        if result.is_err() {
+            self.appended.truncate(appended_len);
+            self.file.set_len(start)?;
+        }
+        result
+    }
+
+    fn write_entries<I>(&mut self, mut offset: u64, entries: I) -> io::Result<()>
+    where
+        I: IntoIterator<Item = (Tlsh, IndexMetadata)>,
+    {
+        let mut writer = BufWriter::new(&mut self.file);
+
+        for (tlsh, metadata) in entries {
+            check_digest(&tlsh, self.bucket_kind, self.checksum_kind)?;
+
+            let mut buf = tlsh.to_bytes();
+            let meta_offset = offset + buf.len() as u64;
+            write_metadata(&mut buf, &metadata)?;
+            writer.write_all(&buf)?;
+
+            offset += buf.len() as u64;
+            self.appended.push((tlsh, meta_offset));
+        }
+
+        writer.flush()
+    }
+
+    /// Returns all entries whose distance to the query does not exceed ```threshold```, ordered
+    /// by distance and then by id. ```with_len``` determines whether the length difference is
+    /// considered (see [`Tlsh::diff`]).
+    ///
+    /// Returns an error with kind [`io::ErrorKind::InvalidData`] if the query has a different
+    /// [`BucketKind`] than the index.
+    pub fn query(
+        &mut self,
+        query: &Tlsh,
+        threshold: usize,
+        with_len: bool,
+    ) -> io::Result<Vec<IndexHit>> {
+        if query.bucket_kind() != self.bucket_kind {
+            Err(TlshError::BucketKindMismatch {
+                left: query.bucket_kind(),
+                right: self.bucket_kind,
+            })?
+        }
+
+        let mut hits = Vec::new();
+        let record_len = self.record_len();
+        let mut buf = Vec::new();
+
+        for partition in &self.partitions {
+            let mut bound = qratio_distance(
+                query.q1ratio() as usize,
+                partition.q1ratio as usize,
+                QRATIO_WEIGHT,
+            ) + qratio_distance(
+                query.q2ratio() as usize,
+                partition.q2ratio as usize,
+                QRATIO_WEIGHT,
+            );
+            if with_len {
+                bound += len_distance(
+                    query.lvalue() as usize,
+                    partition.lvalue as usize,
+                    LEN_WEIGHT,
+                );
+            }
+            if bound > threshold {
+                continue;
+            }
+
+            buf.resize(partition.count as usize * record_len, 0);
+            self.file.seek(SeekFrom::Start(
+                HEADER_LEN + partition.start * record_len as u64,
+            ))?;
+            self.file.read_exact(&mut buf)?;
+
+            for (ii, record) in buf.chunks(record_len).enumerate() {
+                let tlsh = Tlsh::from_bytes(&record[..record_len - META_OFFSET_LEN])?;
+                if let Some(distance) = query.diff_within(&tlsh, with_len, threshold) {
+                    hits.push(IndexHit {
+                        id: partition.start as usize + ii,
+                        distance,
+                        tlsh,
+                    });
+                }
+            }
+        }
+
+        for (ii, (tlsh, _)) in self.appended.iter().enumerate() {
+            if let Some(distance) = query.diff_within(tlsh, with_len, threshold) {
+                hits.push(IndexHit {
+                    id: self.record_count as usize + ii,
+                    distance,
+                    tlsh: tlsh.clone(),
+                });
+            }
+        }
+
+        hits.sort_unstable_by_key(|h| (h.distance, h.id));
+        Ok(hits)
+    }
+
+    /// Loads the digest of the entry with the given id.
+    ///
+    /// # Panics
+    /// Panics if ```id``` is out of bounds.
+    pub fn digest(&mut self, id: usize) -> io::Result<Tlsh> {
+        assert!(id < self.len(), "Id {} is out of bounds", id);
+
+        if let Some(ii) = id.checked_sub(self.record_count as usize) {
+            return Ok(self.appended[ii].0.clone());
+        }
+
+        let mut buf = vec![0u8; self.record_len() - META_OFFSET_LEN];
+        self.seek_record(id)?;
+        self.file.read_exact(&mut buf)?;
+        Ok(Tlsh::from_bytes(&buf)?)
+    }
+
+    /// Loads the metadata of the entry with the given id.
+    ///
+    /// # Panics
+    /// Panics if ```id``` is out of bounds.
+    pub fn metadata(&mut self, id: usize) -> io::Result<IndexMetadata> {
+        assert!(id < self.len(), "Id {} is out of bounds", id);
+
+        let offset = match id.checked_sub(self.record_count as usize) {
+            Some(ii) => self.appended[ii].1,
+            None => {
+                let mut buf = [0u8; META_OFFSET_LEN];
+                self.seek_record(id)?;
+                self.file.seek(SeekFrom::Current(
+                    (self.record_len() - META_OFFSET_LEN) as i64,
+                ))?;
+                self.file.read_exact(&mut buf)?;
+                read_u64(&buf)
+            }
+        };
+
+        self.file.seek(SeekFrom::Start(offset))?;
+        read_metadata(&mut BufReader::new(&mut self.file))
+    }
+
+    /// Rewrites the index file such that all entries, including appended ones, are covered by the
+    /// partition table. The new file is written next to the index and then renamed over it, so the
+    /// index file is never left in a partially written state.
+    ///
+    /// Entries are renumbered in the order of the new records.
+    pub fn compact(&mut self) -> io::Result<()> {
+        self.check_writable()?;
+
+        let mut entries = Vec::with_capacity(self.len());
+        for id in 0..self.len() {
+            entries.push((self.digest(id)?, id));
+        }
+
+        let path = self.path.clone();
+        replace_index(
+            &path,
+            self.bucket_kind,
+            self.checksum_kind,
+            &mut entries,
+            |id| self.metadata(id),
+        )?;
+
+        *self = Self::open(&self.path)?;
+        Ok(())
+    }
+
+    fn check_writable(&self) -> io::Result<()> {
+        if self.read_only {
+            Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "Index was opened read-only",
+            ))?
+        }
+        Ok(())
+    }
+
+    fn record_len(&self) -> usize {
+        binary_len(self.bucket_kind, self.checksum_kind) + META_OFFSET_LEN
+    }
+
+    fn seek_record(&mut self, id: usize) -> io::Result<u64> {
+        self.file.seek(SeekFrom::Start(
+            HEADER_LEN + (id * self.record_len()) as u64,
+        ))
+    }
+}
+
+/// Same as [`write_index`], but writes the index to a temporary file next to ```path``` first and
+/// then renames it over ```path```, so that ```path``` is never left in a partially written state.
+pub(crate) fn replace_index<F>(
+    path: &Path,
+    bucket_kind: BucketKind,
+    checksum_kind: ChecksumKind,
+    entries: &mut [(Tlsh, usize)],
+    metadata: F,
+) -> io::Result<()>
+where
+    F: FnMut(usize) -> io::Result<IndexMetadata>,
+{
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+    // The name is unique per process and call, so that concurrent writers never share a file.
+    let mut tmp = path.to_path_buf().into_os_string();
+    tmp.push(format!(
+        ".{}.{}.tmp",
+        process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    let tmp = PathBuf::from(tmp);
+
+    let result = write_index(&tmp, bucket_kind, checksum_kind, entries, metadata)
+        .and_then(|_| fs::rename(&tmp, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    result
+}
+
+/// Writes a compacted index file with the given entries, which are sorted into partitions in
+/// place. ```metadata``` loads the metadata of an entry by the id attached to it.
+fn write_index<F>(
+    path: &Path,
+    bucket_kind: BucketKind,
+    checksum_kind: ChecksumKind,
+    entries: &mut [(Tlsh, usize)],
+    mut metadata: F,
+) -> io::Result<()>
+where
+    F: FnMut(usize) -> io::Result<IndexMetadata>,
+{
+    entries.sort_by_key(|(tlsh, id)| (tlsh.lvalue(), tlsh.q1ratio(), tlsh.q2ratio(), *id));
+
+    let mut partitions: Vec<Partition> = Vec::new();
+    for (ii, (tlsh, _)) in entries.iter().enumerate() {
+        match partitions.last_mut() {
+            Some(p)
+                if (p.lvalue, p.q1ratio, p.q2ratio)
+                    == (tlsh.lvalue(), tlsh.q1ratio(), tlsh.q2ratio()) =>
+            {
+                p.count += 1
+            }
+            _ => partitions.push(Partition {
+                lvalue: tlsh.lvalue(),
+                q1ratio: tlsh.q1ratio(),
+                q2ratio: tlsh.q2ratio(),
+                start: ii as u64,
+                count: 1,
+            }),
+        }
+    }
+
+    let mut metadata_buf = Vec::new();
+    let mut metadata_offsets = Vec::with_capacity(entries.len());
+    let record_len = (binary_len(bucket_kind, checksum_kind) + META_OFFSET_LEN) as u64;
+    let metadata_offset =
+        HEADER_LEN + entries.len() as u64 * record_len + partitions.len() as u64 * PARTITION_LEN;
+    for &(_, id) in entries.iter() {
+        metadata_offsets.push(metadata_offset + metadata_buf.len() as u64);
+        write_metadata(&mut metadata_buf, &metadata(id)?)?;
+    }
+
+    let mut writer = BufWriter::new(File::create(path)?);
+
+    writer.write_all(MAGIC)?;
+    writer.write_all(&FORMAT_VERSION.to_le_bytes())?;
+    writer.write_all(&[encode_config(bucket_kind, checksum_kind), 0, 0, 0])?;
+    writer.write_all(&(entries.len() as u64).to_le_bytes())?;
+    writer.write_all(&(partitions.len() as u64).to_le_bytes())?;
+    writer.write_all(&(metadata_buf.len() as u64).to_le_bytes())?;
+
+    for ((tlsh, _), offset) in entries.iter().zip(&metadata_offsets) {
+        writer.write_all(&tlsh.to_bytes())?;
+        writer.write_all(&offset.to_le_bytes())?;
+    }
+
+    for p in &partitions {
+        writer.write_all(&[p.lvalue, p.q1ratio, p.q2ratio, 0])?;
+        writer.write_all(&p.start.to_le_bytes())?;
+        writer.write_all(&p.count.to_le_bytes())?;
+    }
+
+    writer.write_all(&metadata_buf)?;
+
+    writer.into_inner().map_err(|e| e.into_error())?.sync_all()
+}
+
+fn encode_config(bucket_kind: BucketKind, checksum_kind: ChecksumKind) -> u8 {
+    (match bucket_kind {
+        BucketKind::Bucket48 => 0,
+        BucketKind::Bucket128 => 1,
+        BucketKind::Bucket256 => 2,
+    }) | match checksum_kind {
+        ChecksumKind::OneByte => 0,
+        ChecksumKind::ThreeByte => 1 << 2,
+    }
+}
+
+fn decode_config(config: u8) -> io::Result<(BucketKind, ChecksumKind)> {
+    let bucket_kind = match config & 0x3 {
+        0 => BucketKind::Bucket48,
+        1 => BucketKind::Bucket128,
+        2 => BucketKind::Bucket256,
+        _ => Err(invalid_data("Invalid index configuration"))?,
+    };
+    let checksum_kind = if config & (1 << 2) == 0 {
+        ChecksumKind::OneByte
+    } else {
+        ChecksumKind::ThreeByte
+    };
+
+    if config >> 3 != 0 || check_config(bucket_kind, checksum_kind).is_err() {
+        Err(invalid_data("Invalid index configuration"))?
+    }
+
+    Ok((bucket_kind, checksum_kind))
+}
+
+fn read_digest<R: Read>(
+    reader: &mut R,
+    bucket_kind: BucketKind,
+    checksum_kind: ChecksumKind,
+) -> io::Result<Tlsh> {
+    let buf = read_bytes(reader, binary_len(bucket_kind, checksum_kind))?;
+    let tlsh = Tlsh::from_bytes(&buf)?;
+    check_digest(&tlsh, bucket_kind, checksum_kind)?;
+
+    Ok(tlsh)
+}
+
+fn write_metadata(buf: &mut Vec<u8>, metadata: &IndexMetadata) -> io::Result<()> {
+    buf.extend_from_slice(&metadata.size.to_le_bytes());
+    for s in &[&metadata.path, &metadata.label] {
+        let len = u32::try_from(s.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Metadata is too long"))?;
+        buf.extend_from_slice(&len.to_le_bytes());
+        buf.extend_from_slice(s.as_bytes());
+    }
+    Ok(())
+}
+
+fn read_metadata<R: Read>(reader: &mut R) -> io::Result<IndexMetadata> {
+    let size = read_u64(&read_bytes(reader, 8)?);
+    let mut strings = Vec::with_capacity(2);
+    for _ in 0..2 {
+        let len = read_u32(&read_bytes(reader, 4)?) as usize;
+        let s = String::from_utf8(read_bytes(reader, len)?)
+            .map_err(|_| invalid_data("Index metadata is not valid UTF-8"))?;
+        strings.push(s);
+    }
+    let label = strings.pop().unwrap_or_default();
+    let path = strings.pop().unwrap_or_default();
+
+    Ok(IndexMetadata { path, size, label })
+}
+
+/// Reads exactly ```len``` bytes. The buffer only grows with the data actually read, so a corrupt
+/// length never causes a large allocation. Returns an error with kind
+/// [`io::ErrorKind::UnexpectedEof`] if the reader ends early.
+fn read_bytes<R: Read>(reader: &mut R, len: usize) -> io::Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    reader.take(len as u64).read_to_end(&mut buf)?;
+    if buf.len() != len {
+        Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "Truncated index entry",
+        ))?
+    }
+    Ok(buf)
+}
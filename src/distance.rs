@@ -0,0 +1,171 @@
+use std::fmt::{self, Display, Formatter};
+
+/// Contribution of each component to the difference between two digests, as returned by
+/// [`Tlsh::diff_breakdown`](crate::Tlsh::diff_breakdown).
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct DiffBreakdown {
+    /// Distance between the lengths. Always zero if the length is not considered.
+    pub len: usize,
+    /// Distance between the q1 ratios.
+    pub q1ratio: usize,
+    /// Distance between the q2 ratios.
+    pub q2ratio: usize,
+    /// Penalty for mismatching checksums, either zero or one.
+    pub checksum: usize,
+    /// Distance between the digest bodies, i.e. the sum of all bucket distances.
+    pub body: usize,
+    /// Codes and distance for each bucket position.
+    pub buckets: Vec<BucketDiff>,
+}
+
+impl DiffBreakdown {
+    /// Returns the total difference, which equals the result of [`Tlsh::diff`](crate::Tlsh::diff).
+    pub fn total(&self) -> usize {
+        self.len + self.q1ratio + self.q2ratio + self.checksum + self.body
+    }
+
+    /// Returns the header part of the difference, i.e. everything except the body distance.
+    pub fn header(&self) -> usize {
+        self.len + self.q1ratio + self.q2ratio + self.checksum
+    }
+
+    /// Renders the bucket distances as a string with one character per bucket: ```.``` if both
+    /// codes are equal, otherwise the distance between the codes (```1```, ```2``` or ```6```).
+    pub fn render_buckets(&self) -> String {
+        self.buckets
+            .iter()
+            .map(|b| match b.distance {
+                0 => '.',
+                d => std::char::from_digit(d as u32, 10).unwrap_or('?'),
+            })
+            .collect()
+    }
+}
+
+impl Display for DiffBreakdown {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        writeln!(f, "total:    {}", self.total())?;
+        writeln!(f, "len:      {}", self.len)?;
+        writeln!(f, "q1ratio:  {}", self.q1ratio)?;
+        writeln!(f, "q2ratio:  {}", self.q2ratio)?;
+        writeln!(f, "checksum: {}", self.checksum)?;
+        writeln!(f, "body:     {}", self.body)?;
+        write!(f, "buckets:  {}", self.render_buckets())
+    }
+}
+
+/// Codes of a single bucket position in two digests and their distance.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct BucketDiff {
+    /// 2-bit code of the left-hand digest.
+    pub left: u8,
+    /// 2-bit code of the right-hand digest.
+    pub right: u8,
+    /// Distance between both codes: ```0```, ```1```, ```2``` or ```6``` if the codes are
+    /// opposite.
+    pub distance: usize,
+}
+
+/// Default weight of the length distance.
+pub(crate) const LEN_WEIGHT: usize = 12;
+/// Default weight of the quartile ratio distances.
+pub(crate) const QRATIO_WEIGHT: usize = 12;
+/// Default penalty for mismatching checksums.
+pub(crate) const CHECKSUM_PENALTY: usize = 1;
+/// Default distance between two opposite 2-bit codes.
+pub(crate) const OPPOSITE_CODE_DISTANCE: usize = 6;
+/// Default distance at which the similarity score reaches zero.
+const MAX_DISTANCE: usize = 300;
+
+/// A scoring profile for [`Tlsh::diff_with`](crate::Tlsh::diff_with) which allows to tune or
+/// disable each component of the difference between two digests.
+///
+/// The default options reproduce [`Tlsh::diff`](crate::Tlsh::diff) including the length
+/// difference.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct DiffOptions {
+    pub(crate) len_weight: usize,
+    pub(crate) len_cap: Option<usize>,
+    pub(crate) qratio_weight: usize,
+    pub(crate) checksum_penalty: usize,
+    pub(crate) opposite_code_distance: usize,
+    pub(crate) max_distance: usize,
+}
+
+impl DiffOptions {
+    /// Constructs options which reproduce [`Tlsh::diff`](crate::Tlsh::diff) including the length
+    /// difference.
+    pub fn new() -> Self {
+        Self {
+            len_weight: LEN_WEIGHT,
+            len_cap: None,
+            qratio_weight: QRATIO_WEIGHT,
+            checksum_penalty: CHECKSUM_PENALTY,
+            opposite_code_distance: OPPOSITE_CODE_DISTANCE,
+            max_distance: MAX_DISTANCE,
+        }
+    }
+
+    /// Sets the weight of the length difference. A length difference of at most one is counted as
+    /// is, larger differences are multiplied by the weight. A weight of zero disables the length
+    /// component. Default: 12.
+    pub fn with_len_weight(mut self, weight: usize) -> Self {
+        self.len_weight = weight;
+        self
+    }
+
+    /// Caps the length component at the given value. Default: no cap.
+    pub fn with_len_cap(mut self, cap: usize) -> Self {
+        self.len_cap = Some(cap);
+        self
+    }
+
+    /// Sets the weight of the q1 and q2 ratio differences. A ratio difference of at most one is
+    /// counted as is, larger differences minus one are multiplied by the weight. A weight of zero
+    /// disables both components. Default: 12.
+    pub fn with_qratio_weight(mut self, weight: usize) -> Self {
+        self.qratio_weight = weight;
+        self
+    }
+
+    /// Sets the penalty for mismatching checksums. A penalty of zero ignores the checksum.
+    /// Default: 1.
+    pub fn with_checksum_penalty(mut self, penalty: usize) -> Self {
+        self.checksum_penalty = penalty;
+        self
+    }
+
+    /// Sets the distance between two opposite 2-bit codes of the digest bodies, i.e. ```00``` and
+    /// ```11```. Other code pairs differ by one or two. Default: 6.
+    pub fn with_opposite_code_distance(mut self, distance: usize) -> Self {
+        self.opposite_code_distance = distance;
+        self
+    }
+
+    /// Sets the distance at which the similarity score reaches zero. Default: 300.
+    ///
+    /// # Panics
+    /// Panics if ```distance``` is zero.
+    pub fn with_max_distance(mut self, distance: usize) -> Self {
+        assert!(distance > 0, "Maximal distance must be positive");
+        self.max_distance = distance;
+        self
+    }
+
+    /// Maps a distance to a similarity score in ```[0, 1]```.
+    ///
+    /// The score decreases linearly from ```1.0``` for identical digests (distance zero) to
+    /// ```0.0``` for digests which are at least the maximal distance apart (see
+    /// [`DiffOptions::with_max_distance`]). The mapping is monotone, so thresholds on the distance
+    /// translate directly to thresholds on the score.
+    pub fn similarity(&self, distance: usize) -> f64 {
+        let d = std::cmp::min(distance, self.max_distance);
+        1.0 - d as f64 / self.max_distance as f64
+    }
+}
+
+impl Default for DiffOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
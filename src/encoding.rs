@@ -0,0 +1,61 @@
+use crate::error::TlshError;
+
+/// URL-safe base64 alphabet (RFC 4648, section 5).
+pub(crate) const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+/// Base32 alphabet (RFC 4648, section 6).
+pub(crate) const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// Encodes a byte array with an alphabet of size ```2^bits```. No padding is appended.
+pub(crate) fn encode(data: &[u8], alphabet: &[u8], bits: u32) -> String {
+    let mask = (1 << bits) - 1;
+    let mut result = String::with_capacity((data.len() * 8).div_ceil(bits as usize));
+    let (mut acc, mut acc_bits) = (0u32, 0);
+
+    for &b in data {
+        acc = (acc << 8) | b as u32;
+        acc_bits += 8;
+
+        while acc_bits >= bits {
+            acc_bits -= bits;
+            result.push(alphabet[((acc >> acc_bits) & mask) as usize] as char);
+        }
+    }
+
+    if acc_bits > 0 {
+        result.push(alphabet[((acc << (bits - acc_bits)) & mask) as usize] as char);
+    }
+
+    result
+}
+
+/// Decodes a string that was encoded by [`encode`] with the same alphabet.
+pub(crate) fn decode(s: &str, alphabet: &[u8], bits: u32) -> Result<Vec<u8>, TlshError> {
+    let mut result = Vec::with_capacity(s.len() * bits as usize / 8);
+    let (mut acc, mut acc_bits) = (0u32, 0);
+
+    for (offset, c) in s.bytes().enumerate() {
+        let value = alphabet
+            .iter()
+            .position(|&a| a == c)
+            .ok_or(TlshError::InvalidHashValue { offset })?;
+
+        acc = (acc << bits) | value as u32;
+        acc_bits += bits;
+
+        if acc_bits >= 8 {
+            acc_bits -= 8;
+            result.push((acc >> acc_bits) as u8);
+        }
+    }
+
+    // Remaining bits are padding, which must be zero and shorter than a character in a
+    // canonical encoding.
+    if acc_bits >= bits || acc & ((1 << acc_bits) - 1) != 0 {
+        Err(TlshError::InvalidHashValue {
+            offset: s.len() - 1,
+        })?
+    }
+
+    Ok(result)
+}
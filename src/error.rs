@@ -1,35 +1,168 @@
-use std::{fmt::Display, num::ParseIntError};
+use std::{fmt::Display, io, num::ParseIntError};
+
+use crate::{BucketKind, ChecksumKind};
 
 /// An enum for possible errors that might occur while calculating hash values.
-#[derive(Debug)]
+///
+/// Every variant carries the context of the failure, e.g. the offending offset in a hash string
+/// or the actual and required input length. Code that only cares about the kind of an error can
+/// ignore the fields with a ```Variant { .. }``` pattern, e.g.
+/// ```TlshError::MinSizeNotReached { .. }```. Matching the former unit variants without braces
+/// no longer compiles.
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum TlshError {
     /// Input's length is too big to handle. Maximal file size is 4GB.
-    DataLenOverflow,
-    /// The hash string is malformed and cannot be parsed.
-    InvalidHashValue,
+    DataLenOverflow {
+        /// Length of the input in bytes.
+        len: usize,
+        /// Maximal supported length in bytes.
+        max: usize,
+    },
+    /// The hash string has a length that matches none of the supported configurations.
+    InvalidHashLength {
+        /// Length of the hash string.
+        len: usize,
+    },
+    /// The hash string is malformed and cannot be parsed, e.g. it has a wrong version prefix or
+    /// a lower-case digit in strict mode.
+    InvalidHashValue {
+        /// Byte offset in the hash string at which the malformed part starts.
+        offset: usize,
+    },
     /// TLSH requires an input of at least 50 bytes.
-    MinSizeNotReached,
+    MinSizeNotReached {
+        /// Length of the input in bytes.
+        len: usize,
+        /// Minimal required length in bytes.
+        required: usize,
+    },
+    /// TLSH in conservative mode requires an input of at least 256 bytes.
+    ConservativeMinSizeNotReached {
+        /// Length of the input in bytes.
+        len: usize,
+        /// Minimal required length in bytes.
+        required: usize,
+    },
+    /// At most half of the buckets (at most 17 of 48) are non-zero, i.e. the input has too little
+    /// variation.
+    TooFewBuckets {
+        /// Number of non-zero buckets.
+        nonzero: usize,
+        /// Number of non-zero buckets which must be exceeded.
+        required: usize,
+    },
+    /// Two digests with a different number of buckets cannot be compared.
+    BucketKindMismatch {
+        /// Bucket kind of the left-hand digest.
+        left: BucketKind,
+        /// Bucket kind of the right-hand digest.
+        right: BucketKind,
+    },
+    /// A digest has a different checksum length than required, e.g. by an index file.
+    ChecksumKindMismatch {
+        /// Checksum kind of the digest.
+        left: ChecksumKind,
+        /// Required checksum kind.
+        right: ChecksumKind,
+    },
+    /// The combination of bucket kind and checksum kind has no digest format in the reference
+    /// implementation.
+    UnsupportedConfiguration {
+        /// Requested bucket kind.
+        bucket_kind: BucketKind,
+        /// Requested checksum kind.
+        checksum_kind: ChecksumKind,
+    },
     /// Fails to parse a hex string to integer.
-    ParseHexFailed,
+    ParseHexFailed {
+        /// Byte offset in the hash string of the digit that cannot be parsed.
+        offset: usize,
+        /// The underlying error.
+        source: ParseIntError,
+    },
 }
 
-impl From<ParseIntError> for TlshError {
-    fn from(_: ParseIntError) -> Self {
-        Self::ParseHexFailed
+impl TlshError {
+    /// Returns the byte offset in the hash string at which parsing failed, if known.
+    pub fn offset(&self) -> Option<usize> {
+        match self {
+            TlshError::InvalidHashValue { offset } | TlshError::ParseHexFailed { offset, .. } => {
+                Some(*offset)
+            }
+            _ => None,
+        }
     }
 }
 
 impl Display for TlshError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            TlshError::DataLenOverflow => {
-                write!(f, "Input file is too big. Maximal file size is 4GB.")
+            TlshError::DataLenOverflow { len, max } => write!(
+                f,
+                "Input file is too big ({} bytes). Maximal file size is {} bytes.",
+                len, max
+            ),
+            TlshError::InvalidHashLength { len } => {
+                write!(f, "Can't parse hash string: invalid length {}", len)
             }
-            TlshError::InvalidHashValue => write!(f, "Can't parse hash string"),
-            TlshError::MinSizeNotReached => {
-                write!(f, "TLSH requires an input of at least 50 bytes.")
+            TlshError::InvalidHashValue { offset } => {
+                write!(f, "Can't parse hash string at offset {}", offset)
+            }
+            TlshError::MinSizeNotReached { len, required } => write!(
+                f,
+                "TLSH requires an input of at least {} bytes, got {} bytes.",
+                required, len
+            ),
+            TlshError::ConservativeMinSizeNotReached { len, required } => write!(
+                f,
+                "TLSH in conservative mode requires an input of at least {} bytes, got {} bytes.",
+                required, len
+            ),
+            TlshError::TooFewBuckets { nonzero, required } => write!(
+                f,
+                "Input has too little variation: {} non-zero buckets, more than {} required.",
+                nonzero, required
+            ),
+            TlshError::BucketKindMismatch { left, right } => write!(
+                f,
+                "Can't compare digests with different bucket kinds: {:?} and {:?}",
+                left, right
+            ),
+            TlshError::ChecksumKindMismatch { left, right } => write!(
+                f,
+                "Digest has checksum kind {:?}, but {:?} is required",
+                left, right
+            ),
+            TlshError::UnsupportedConfiguration {
+                bucket_kind,
+                checksum_kind,
+            } => write!(
+                f,
+                "Unsupported configuration: {:?} with {:?}",
+                bucket_kind, checksum_kind
+            ),
+            TlshError::ParseHexFailed { offset, .. } => {
+                write!(
+                    f,
+                    "Can't convert hex string to integer at offset {}",
+                    offset
+                )
             }
-            TlshError::ParseHexFailed => write!(f, "Can't convert hex string to integer"),
         }
     }
 }
+
+impl std::error::Error for TlshError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            TlshError::ParseHexFailed { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<TlshError> for io::Error {
+    fn from(e: TlshError) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, e)
+    }
+}
@@ -1,8 +1,15 @@
-use std::ops::{Add, Sub};
+use std::{
+    io,
+    ops::{Add, Sub},
+};
 
-use crate::error::TlshError;
+use crate::{error::TlshError, BucketKind, ChecksumKind, Tlsh};
 
 pub(crate) const BUCKET_SIZE: usize = 256;
+/// Minimal input length in non-conservative mode.
+pub(crate) const MIN_DATA_LEN: usize = 50;
+/// Minimal input length in conservative mode.
+pub(crate) const MIN_CONSERVATIVE_DATA_LEN: usize = 256;
 /// Size of a sliding window to process a byte string and populate an array of bucket counts.
 pub(crate) const WINDOW_SIZE: usize = 5;
 
@@ -41,10 +48,15 @@ pub(crate) const TOPVAL: [usize; 170] = [
     2622945920, 2885240448, 3173764736, 3491141248, 3840255616, 4224281216,
 ];
 
-const _MAX_DATA_LEN: usize = TOPVAL[TOPVAL.len() - 1];
+const MAX_DATA_LEN: usize = TOPVAL[TOPVAL.len() - 1];
 
-static mut BIT_PAIRS_FLAG: bool = false;
-static mut BIT_PAIRS_DIFF: [[usize; 256]; 256] = [[0; 256]; 256];
+/// Lookup table for the distance between two bytes of a digest body, where each byte consists of
+/// four 2-bit codes. The table is computed at compile time.
+static BIT_PAIRS_DIFF: [[u8; 256]; 256] = bit_pairs_diff_table();
+
+/// Lookup table for the L1 distance between two bytes of a digest body, i.e. the sum of the
+/// absolute differences of their 2-bit codes. The table is computed at compile time.
+static BIT_PAIRS_L1: [[u8; 256]; 256] = bit_pairs_l1_table();
 
 pub(crate) fn pearson_hash(salt: u8, ii: u8, jj: u8, kk: u8) -> u8 {
     let mut h = 0;
@@ -91,8 +103,8 @@ pub(crate) fn find_quartiles(buckets: &[u32], bucket_count: usize) -> (u32, u32,
 
     let mut q1 = 0;
     low = 0;
-    for ii in 0..spl {
-        high = shortcut_low[ii];
+    for &sl in shortcut_low.iter().take(spl + 1) {
+        high = sl;
 
         if high > p1 {
             q1 = loop {
@@ -116,8 +128,8 @@ pub(crate) fn find_quartiles(buckets: &[u32], bucket_count: usize) -> (u32, u32,
 
     let mut q3 = 0;
     high = end;
-    for ii in 0..sph {
-        low = shortcut_high[ii];
+    for &sh in shortcut_high.iter().take(sph + 1) {
+        low = sh;
         if low < p3 {
             q3 = loop {
                 let pivot = partition(&mut buckets_copy, low, high);
@@ -168,7 +180,7 @@ pub(crate) fn partition(buckets: &mut [u32], low: usize, high: usize) -> usize {
     buckets[high] = buckets[result];
     buckets[result] = val;
 
-    return result;
+    result
 }
 
 pub(crate) fn l_capturing(len: usize) -> Result<usize, TlshError> {
@@ -193,7 +205,10 @@ pub(crate) fn l_capturing(len: usize) -> Result<usize, TlshError> {
         idx = (bottom + top) >> 1;
     }
 
-    Err(TlshError::DataLenOverflow)
+    Err(TlshError::DataLenOverflow {
+        len,
+        max: MAX_DATA_LEN,
+    })
 }
 
 pub(crate) fn mod_diff<T>(x: T, y: T, circ_q: T) -> T
@@ -209,41 +224,163 @@ where
     std::cmp::min(dl, dr)
 }
 
+/// Length component of the difference between two digests.
+pub(crate) fn len_distance(x: usize, y: usize, weight: usize) -> usize {
+    match mod_diff(x, y, 256) {
+        _ if weight == 0 => 0,
+        x @ 0..=1 => x,
+        x => x * weight,
+    }
+}
+
+/// Quartile ratio component of the difference between two digests.
+pub(crate) fn qratio_distance(x: usize, y: usize, weight: usize) -> usize {
+    match mod_diff(x, y, 16) {
+        _ if weight == 0 => 0,
+        x @ 0..=1 => x,
+        x => (x - 1) * weight,
+    }
+}
+
 pub(crate) fn bit_distance(x: &[u8], y: &[u8]) -> usize {
+    x.iter()
+        .zip(y)
+        .map(|(&a, &b)| BIT_PAIRS_DIFF[a as usize][b as usize] as usize)
+        .sum()
+}
+
+/// L1 distance between two digest bodies. Unlike [`bit_distance`], this is a metric and it never
+/// exceeds [`bit_distance`].
+pub(crate) fn l1_bit_distance(x: &[u8], y: &[u8]) -> usize {
+    x.iter()
+        .zip(y)
+        .map(|(&a, &b)| BIT_PAIRS_L1[a as usize][b as usize] as usize)
+        .sum()
+}
+
+/// Same as [`bit_distance`], but returns ```None``` as soon as the distance exceeds ```cutoff```.
+pub(crate) fn bounded_bit_distance(x: &[u8], y: &[u8], cutoff: usize) -> Option<usize> {
+    let mut result = 0;
+
+    // Checking the cutoff once per chunk keeps the inner loop free of branches.
+    for (cx, cy) in x.chunks(8).zip(y.chunks(8)) {
+        result += bit_distance(cx, cy);
+        if result > cutoff {
+            return None;
+        }
+    }
+
+    Some(result)
+}
+
+/// Same as [`bit_distance`], but opposite codes have the given distance instead of 6.
+pub(crate) fn weighted_bit_distance(x: &[u8], y: &[u8], opposite: usize) -> usize {
     let mut result = 0;
 
-    for ii in 0..x.len() {
-        unsafe {
-            result += bit_pairs_diff(x[ii] as usize, y[ii] as usize);
+    for (&a, &b) in x.iter().zip(y) {
+        for kk in 0..4 {
+            result += match code_distance((a >> (kk * 2)) & 0x3, (b >> (kk * 2)) & 0x3) {
+                6 => opposite,
+                d => d,
+            };
         }
     }
 
     result
 }
 
-#[inline]
-unsafe fn bit_pairs_diff(row: usize, col: usize) -> usize {
-    let f = |x: &mut i16, y: &mut i16, diff: &mut i16| {
-        let d = (*x % 4 - *y % 4).abs();
-        *diff += if d == 3 { 6 } else { d };
+/// Distance between two 2-bit codes of a digest body.
+// `abs_diff` is avoided here, since the extra steps per call make the evaluation of
+// `BIT_PAIRS_DIFF`, which calls this function for each of the 262144 pairs of codes, trip the
+// long-running const evaluation lint. `bit_pairs_l1_table` does less work per pair and can use it.
+#[allow(clippy::manual_abs_diff)]
+pub(crate) const fn code_distance(x: u8, y: u8) -> usize {
+    let d = if x > y { x - y } else { y - x };
+    if d == 3 {
+        6
+    } else {
+        d as usize
+    }
+}
 
-        *x /= 4;
-        *y /= 4;
-    };
+const fn bit_pairs_diff_table() -> [[u8; 256]; 256] {
+    let mut table = [[0; 256]; 256];
+
+    let mut ii = 0;
+    while ii < 256 {
+        let mut jj = 0;
+        while jj < 256 {
+            let (mut diff, mut kk) = (0, 0);
+            while kk < 4 {
+                let (x, y) = ((ii >> (kk * 2)) & 0x3, (jj >> (kk * 2)) & 0x3);
+                diff += code_distance(x as u8, y as u8);
+                kk += 1;
+            }
 
-    if !BIT_PAIRS_FLAG {
-        for ii in 0..256i16 {
-            for jj in 0..256 {
-                let (mut x, mut y, mut diff) = (ii, jj, 0);
-                for _ in 0..4 {
-                    f(&mut x, &mut y, &mut diff);
-                }
+            table[ii][jj] = diff as u8;
+            jj += 1;
+        }
+        ii += 1;
+    }
 
-                BIT_PAIRS_DIFF[ii as usize][jj as usize] = diff as usize;
+    table
+}
+
+const fn bit_pairs_l1_table() -> [[u8; 256]; 256] {
+    let mut table = [[0; 256]; 256];
+
+    let mut ii: usize = 0;
+    while ii < 256 {
+        let mut jj = 0;
+        while jj < 256 {
+            let (mut diff, mut kk) = (0, 0);
+            while kk < 4 {
+                let (x, y) = ((ii >> (kk * 2)) & 0x3, (jj >> (kk * 2)) & 0x3);
+                diff += x.abs_diff(y);
+                kk += 1;
             }
+
+            table[ii][jj] = diff as u8;
+            jj += 1;
         }
-        BIT_PAIRS_FLAG = true;
+        ii += 1;
     }
 
-    BIT_PAIRS_DIFF[row][col]
+    table
+}
+
+pub(crate) fn check_digest(
+    tlsh: &Tlsh,
+    bucket_kind: BucketKind,
+    checksum_kind: ChecksumKind,
+) -> Result<(), TlshError> {
+    if tlsh.bucket_kind() != bucket_kind {
+        Err(TlshError::BucketKindMismatch {
+            left: tlsh.bucket_kind(),
+            right: bucket_kind,
+        })?
+    }
+    if tlsh.checksum_kind() != checksum_kind {
+        Err(TlshError::ChecksumKindMismatch {
+            left: tlsh.checksum_kind(),
+            right: checksum_kind,
+        })?
+    }
+    Ok(())
+}
+
+pub(crate) fn read_u32(bytes: &[u8]) -> u32 {
+    let mut buf = [0u8; 4];
+    buf.copy_from_slice(&bytes[..4]);
+    u32::from_le_bytes(buf)
+}
+
+pub(crate) fn read_u64(bytes: &[u8]) -> u64 {
+    let mut buf = [0u8; 8];
+    buf.copy_from_slice(&bytes[..8]);
+    u64::from_le_bytes(buf)
+}
+
+pub(crate) fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
 }
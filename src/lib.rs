@@ -3,24 +3,27 @@
 //! and measure the difference between two hash values.
 //!
 //! The current implementation of TLSH has two conditions on the file size:
-//! - the input must be at least 50 bytes long
+//! - the input must be at least 50 bytes long (256 bytes in [`HashingMode::Conservative`])
 //! - the size must not exceed 4Gb.
 //!
+//! Additionally, more than half of the buckets must be non-zero after processing the input.
+//! Otherwise, the input is considered to have too little information for a meaningful digest.
+//!
 //! ## Algorithm
 //!
 //! The algorithm to construct a TLSH digest is as follows (for more detail, see [J. Oliver et al.](https://documents.trendmicro.com/assets/wp/wp-locality-sensitive-hash.pdf)):
 //! - **Step 1**: processes an input stream by using a sliding window of length 5 and populates the hash buckets.
-//! Each triplet is passed through a hash function (in this implementation, the hash function is the  [Pearson hashing](https://en.wikipedia.org/wiki/Pearson_hashing)).
+//!   Each triplet is passed through a hash function (in this implementation, the hash function is the  [Pearson hashing](https://en.wikipedia.org/wiki/Pearson_hashing)).
 //! - **Step 2**: calculates the quartile points from the hash bucket obtained in step 1. This step might requires the sorting of the bucket array:
-//! ```q1```: the lowest 25% of the array
-//! ```q2```: the lowest 50% of the array
-//! ```q3```: the lowest 75% of the array
+//!   ```q1```: the lowest 25% of the array
+//!   ```q2```: the lowest 50% of the array
+//!   ```q3```: the lowest 75% of the array
 //! - **Step 3**: computes the digest header. The first three bytes of a hash is reserved for the header. The header of a TLSH hash consists of three parts:
 //! - The first byte is a checksum (with some modulo) of the byte string
 //! - The second byte is computed from the logarithm of the byte string's length (with some modulo)
 //! - The third byte is the result of ```q1_ratio <<< 4 | q2_ratio```, where  
-//!     ```q1_ratio =  (q1 * 100 / q3) MOD 16```  
-//!     ```q2_ratio =  (q2 * 100 / q3) MOD 16```  
+//!   ```q1_ratio =  (q1 * 100 / q3) MOD 16```  
+//!   ```q2_ratio =  (q2 * 100 / q3) MOD 16```  
 //! - **Step 4**: constructs the digest body from the bucket array. Note: in this step, the reversing order in reading the bucket is assumed. This means, the last element is read first while the first is read last. Their value is converted into hex form and appended into the final hash value.
 //!
 //! ## Examples
@@ -51,16 +54,65 @@
 //! let _ = tlsh1.diff(&tlsh2, true);
 //! // Calculate diff between s1 & s2, excluding length difference.
 //! let _ = tlsh1.diff(&tlsh2, false);
+//!
+//! // Convert a hash value to its hex-encoded string and parse it back.
+//! let parsed: Tlsh = tlsh1.to_string().parse().unwrap();
+//! assert_eq!(tlsh1, parsed);
+#![forbid(unsafe_code)]
+
+mod calibration;
+pub use calibration::{Calibration, DistanceTable, RateEstimate, ThresholdReport};
+
+mod cluster;
+pub use cluster::{Clustering, Dbscan, HacT, Optics, OpticsOrdering};
+
+mod concurrent;
+pub use concurrent::{ConcurrentIndex, OwnedNeighbour};
+
+mod distance;
+pub use distance::{BucketDiff, DiffBreakdown, DiffOptions};
+
+mod digest_set;
+pub use digest_set::DigestSet;
+
+mod disk_index;
+pub use disk_index::{DiskIndex, IndexHit, IndexMetadata};
+
+mod encoding;
 mod helper;
 
 mod error;
 pub use error::TlshError;
 
+mod lsh;
+pub use lsh::{LshIndex, LshParams, QueryStats};
+
+mod matrix;
+pub use matrix::{DistanceMatrix, JoinPair, SimilarityJoin};
+
+mod online;
+pub use online::{MaintenanceReport, OnlineClusterer};
+
+mod prefilter;
+pub use prefilter::HeaderPrefilter;
+
 mod tlsh;
+pub use crate::tlsh::hash_bytes;
 pub use crate::tlsh::BucketKind;
 pub use crate::tlsh::ChecksumKind;
+pub use crate::tlsh::HashingMode;
+pub use crate::tlsh::ParseMode;
 pub use crate::tlsh::Tlsh;
 pub use crate::tlsh::TlshBuilder;
 pub use crate::tlsh::Version;
 
+mod vptree;
+pub use vptree::{Neighbour, VpTree};
+
+#[cfg(feature = "serde")]
+mod serde_impl;
+
+#[cfg(feature = "tokio")]
+mod async_io;
+
 mod tests;
@@ -0,0 +1,226 @@
+use std::collections::HashMap;
+
+use crate::{BucketKind, Neighbour, Tlsh, TlshError};
+
+/// Parameters of an [`LshIndex`], determining the trade-off between recall and speed.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct LshParams {
+    band_size: usize,
+    probe_radius: usize,
+}
+
+impl LshParams {
+    /// Constructs parameters with bands of 4 bytes (16 codes) and a probe radius of 1.
+    pub fn new() -> Self {
+        Self {
+            band_size: 4,
+            probe_radius: 1,
+        }
+    }
+
+    /// Sets the number of body bytes per band. Smaller bands produce more candidates, which
+    /// increases recall at the cost of speed. Default: 4.
+    ///
+    /// # Panics
+    /// Panics if ```size``` is zero.
+    pub fn with_band_size(mut self, size: usize) -> Self {
+        assert!(size > 0, "Band size must be positive");
+        self.band_size = size;
+        self
+    }
+
+    /// Sets the maximal number of codes per band which are changed by one when probing
+    /// neighbouring buckets. A radius of zero only probes the bucket of the query itself. Each
+    /// additional step increases recall, but also the number of probes per band considerably.
+    /// Default: 1.
+    pub fn with_probe_radius(mut self, radius: usize) -> Self {
+        self.probe_radius = radius;
+        self
+    }
+}
+
+impl Default for LshParams {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Statistics of a single query against an [`LshIndex`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct QueryStats {
+    /// Number of buckets looked up over all bands.
+    pub probes: usize,
+    /// Number of distinct digests verified with [`Tlsh::diff`].
+    pub candidates: usize,
+}
+
+/// An index which finds similar digests in near-constant time by locality sensitive hashing of
+/// their bodies.
+///
+/// The body of each digest is split into bands of consecutive bytes and each band is hashed into
+/// its own table. A query looks up the buckets of its own bands and, depending on the probe radius
+/// (see [`LshParams`]), of bands in which some codes differ by one. All digests found this way are
+/// verified with [`Tlsh::diff`]. The results are therefore exact in distance, but digests which
+/// share no probed band with the query are missed. Use [`QueryStats`] and a brute-force
+/// comparison on a sample to measure the recall for given parameters.
+#[derive(Clone, Debug)]
+pub struct LshIndex<T> {
+    params: LshParams,
+    with_len: bool,
+    bucket_kind: Option<BucketKind>,
+    items: Vec<(Tlsh, T)>,
+    tables: Vec<HashMap<Vec<u8>, Vec<usize>>>,
+}
+
+impl<T> LshIndex<T> {
+    /// Constructs an empty index. ```with_len``` determines whether the length difference is
+    /// considered when verifying candidates (see [`Tlsh::diff`]).
+    pub fn new(params: LshParams, with_len: bool) -> Self {
+        Self {
+            params,
+            with_len,
+            bucket_kind: None,
+            items: Vec::new(),
+            tables: Vec::new(),
+        }
+    }
+
+    /// Returns the number of digests in the index.
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    /// Returns ```true``` if the index contains no digests.
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// Adds a digest with its payload to the index.
+    ///
+    /// Returns an error if the digest has a different [`BucketKind`] than the digests in the
+    /// index.
+    pub fn insert(&mut self, tlsh: Tlsh, value: T) -> Result<(), TlshError> {
+        self.check_query(&tlsh)?;
+
+        if self.bucket_kind.is_none() {
+            self.bucket_kind = Some(tlsh.bucket_kind());
+            let band_count = tlsh.codes().len().div_ceil(self.params.band_size);
+            self.tables = vec![HashMap::new(); band_count];
+        }
+
+        let id = self.items.len();
+        for (table, band) in self
+            .tables
+            .iter_mut()
+            .zip(tlsh.codes().chunks(self.params.band_size))
+        {
+            table.entry(band.to_vec()).or_default().push(id);
+        }
+        self.items.push((tlsh, value));
+
+        Ok(())
+    }
+
+    /// Returns all digests found by probing whose distance to the query does not exceed
+    /// ```threshold```, ordered by distance and then by insertion order.
+    ///
+    /// Returns an error if the query has a different [`BucketKind`] than the digests in the index.
+    pub fn query(
+        &self,
+        query: &Tlsh,
+        threshold: usize,
+    ) -> Result<Vec<Neighbour<'_, T>>, TlshError> {
+        Ok(self.query_with_stats(query, threshold)?.0)
+    }
+
+    /// Same as [`LshIndex::query`], but additionally returns statistics of the query.
+    pub fn query_with_stats(
+        &self,
+        query: &Tlsh,
+        threshold: usize,
+    ) -> Result<(Vec<Neighbour<'_, T>>, QueryStats), TlshError> {
+        self.check_query(query)?;
+
+        let mut stats = QueryStats::default();
+        let mut candidates = Vec::new();
+
+        for (table, band) in self
+            .tables
+            .iter()
+            .zip(query.codes().chunks(self.params.band_size))
+        {
+            let mut band = band.to_vec();
+            probe(table, &mut band, 0, self.params.probe_radius, &mut |ids| {
+                stats.probes += 1;
+                candidates.extend_from_slice(ids);
+            });
+        }
+
+        candidates.sort_unstable();
+        candidates.dedup();
+        stats.candidates = candidates.len();
+
+        let mut hits: Vec<(usize, usize)> = candidates
+            .into_iter()
+            .filter_map(|id| {
+                query
+                    .diff_within(&self.items[id].0, self.with_len, threshold)
+                    .map(|d| (d, id))
+            })
+            .collect();
+        hits.sort_unstable();
+
+        let result = hits
+            .into_iter()
+            .map(|(distance, id)| Neighbour {
+                distance,
+                tlsh: &self.items[id].0,
+                value: &self.items[id].1,
+            })
+            .collect();
+
+        Ok((result, stats))
+    }
+
+    fn check_query(&self, query: &Tlsh) -> Result<(), TlshError> {
+        match self.bucket_kind {
+            Some(bk) if bk != query.bucket_kind() => Err(TlshError::BucketKindMismatch {
+                left: query.bucket_kind(),
+                right: bk,
+            }),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Looks up ```band``` and, recursively, all bands in which at most ```radius``` further codes at
+/// positions from ```start``` on differ by one. ```visit``` is called once per lookup.
+fn probe<'a, F>(
+    table: &'a HashMap<Vec<u8>, Vec<usize>>,
+    band: &mut Vec<u8>,
+    start: usize,
+    radius: usize,
+    visit: &mut F,
+) where
+    F: FnMut(&'a [usize]),
+{
+    visit(table.get(band.as_slice()).map_or(&[], |ids| ids.as_slice()));
+
+    if radius == 0 {
+        return;
+    }
+
+    for pos in start..(band.len() * 4) {
+        let (byte, shift) = (pos / 4, (pos % 4) * 2);
+        let original = band[byte];
+        let code = (original >> shift) & 0x3;
+
+        for &neighbour in &[code.wrapping_sub(1), code + 1] {
+            if neighbour <= 3 {
+                band[byte] = (original & !(0x3 << shift)) | (neighbour << shift);
+                probe(table, band, pos + 1, radius - 1, visit);
+            }
+        }
+        band[byte] = original;
+    }
+}
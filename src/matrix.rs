@@ -0,0 +1,249 @@
+use std::{
+    io::{self, Write},
+    thread,
+};
+
+use crate::{Tlsh, TlshError};
+
+/// Distances between all pairs of a collection of digests.
+///
+/// Only the upper triangle without the diagonal is stored (the condensed form), i.e. the distances
+/// ```(0, 1), (0, 2), ..., (0, n - 1), (1, 2), ..., (n - 2, n - 1)``` in this order.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct DistanceMatrix {
+    len: usize,
+    condensed: Vec<usize>,
+}
+
+impl DistanceMatrix {
+    /// Computes the distances between all pairs of digests using all available cores.
+    /// ```with_len``` determines whether the length difference is considered (see
+    /// [`Tlsh::diff`]).
+    ///
+    /// Returns an error if the digests have different [`BucketKind`](crate::BucketKind)s.
+    pub fn new(digests: &[Tlsh], with_len: bool) -> Result<Self, TlshError> {
+        Self::with_threads(digests, with_len, default_threads())
+    }
+
+    /// Same as [`DistanceMatrix::new`], but uses the given number of threads.
+    ///
+    /// # Panics
+    /// Panics if ```threads``` is zero.
+    pub fn with_threads(
+        digests: &[Tlsh],
+        with_len: bool,
+        threads: usize,
+    ) -> Result<Self, TlshError> {
+        assert!(threads > 0, "Number of threads must be positive");
+        check_bucket_kinds(digests)?;
+
+        let len = digests.len();
+        let mut condensed = vec![0; len * len.saturating_sub(1) / 2];
+
+        // Rows get shorter towards the end, so they are distributed round-robin for balance.
+        let mut shares: Vec<Vec<(usize, &mut [usize])>> =
+            (0..threads).map(|_| Vec::new()).collect();
+        let mut rest = condensed.as_mut_slice();
+        for ii in 0..len {
+            let (row, tail) = rest.split_at_mut(len - ii - 1);
+            shares[ii % threads].push((ii, row));
+            rest = tail;
+        }
+
+        thread::scope(|s| {
+            for share in shares {
+                s.spawn(move || {
+                    for (ii, row) in share {
+                        for (d, other) in row.iter_mut().zip(&digests[ii + 1..]) {
+                            *d = digests[ii].diff(other, with_len);
+                        }
+                    }
+                });
+            }
+        });
+
+        Ok(Self { len, condensed })
+    }
+
+    /// Returns the number of digests.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns ```true``` if the matrix was computed from an empty collection.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the distance between the digests at positions ```x``` and ```y```.
+    ///
+    /// # Panics
+    /// Panics if ```x``` or ```y``` is out of bounds.
+    pub fn get(&self, x: usize, y: usize) -> usize {
+        assert!(
+            x < self.len && y < self.len,
+            "Position ({}, {}) is out of bounds",
+            x,
+            y
+        );
+
+        match (x.min(y), x.max(y)) {
+            (x, y) if x == y => 0,
+            (x, y) => self.condensed[x * (2 * self.len - x - 1) / 2 + (y - x - 1)],
+        }
+    }
+
+    /// Returns the condensed form of the matrix.
+    pub fn condensed(&self) -> &[usize] {
+        &self.condensed
+    }
+
+    /// Returns the full matrix in row-major order.
+    pub fn to_full(&self) -> Vec<usize> {
+        let mut result = Vec::with_capacity(self.len * self.len);
+        for x in 0..self.len {
+            result.extend((0..self.len).map(|y| self.get(x, y)));
+        }
+        result
+    }
+
+    /// Writes the full matrix as delimited text, e.g. with ```','``` as delimiter for CSV or
+    /// ```'\t'``` for TSV. The first row and the first column contain the positions of the
+    /// digests.
+    pub fn write_delimited<W: Write>(&self, writer: &mut W, delimiter: char) -> io::Result<()> {
+        for y in 0..self.len {
+            write!(writer, "{}{}", delimiter, y)?;
+        }
+        writeln!(writer)?;
+
+        for x in 0..self.len {
+            write!(writer, "{}", x)?;
+            for y in 0..self.len {
+                write!(writer, "{}{}", delimiter, self.get(x, y))?;
+            }
+            writeln!(writer)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A pair of digests found by a [`SimilarityJoin`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct JoinPair {
+    /// Position of the digest in the left collection.
+    pub left: usize,
+    /// Position of the digest in the right collection.
+    pub right: usize,
+    /// Distance between both digests as computed by [`Tlsh::diff`].
+    pub distance: usize,
+}
+
+/// All pairs of digests from two collections whose distance does not exceed a threshold.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct SimilarityJoin {
+    pairs: Vec<JoinPair>,
+}
+
+impl SimilarityJoin {
+    /// Compares each digest of ```left``` with each digest of ```right``` using all available cores
+    /// and keeps all pairs whose distance does not exceed ```threshold```. ```with_len```
+    /// determines whether the length difference is considered (see [`Tlsh::diff`]).
+    ///
+    /// Returns an error if the digests have different [`BucketKind`](crate::BucketKind)s.
+    pub fn new(
+        left: &[Tlsh],
+        right: &[Tlsh],
+        threshold: usize,
+        with_len: bool,
+    ) -> Result<Self, TlshError> {
+        Self::with_threads(left, right, threshold, with_len, default_threads())
+    }
+
+    /// Same as [`SimilarityJoin::new`], but uses the given number of threads.
+    ///
+    /// # Panics
+    /// Panics if ```threads``` is zero.
+    pub fn with_threads(
+        left: &[Tlsh],
+        right: &[Tlsh],
+        threshold: usize,
+        with_len: bool,
+        threads: usize,
+    ) -> Result<Self, TlshError> {
+        assert!(threads > 0, "Number of threads must be positive");
+        check_bucket_kinds(left.iter().chain(right))?;
+
+        let chunk_size = left.len().div_ceil(threads).max(1);
+        let pairs = thread::scope(|s| {
+            let handles: Vec<_> = left
+                .chunks(chunk_size)
+                .enumerate()
+                .map(|(chunk, digests)| {
+                    s.spawn(move || {
+                        let mut pairs = Vec::new();
+                        for (ii, x) in digests.iter().enumerate() {
+                            for (jj, y) in right.iter().enumerate() {
+                                if let Some(distance) = x.diff_within(y, with_len, threshold) {
+                                    pairs.push(JoinPair {
+                                        left: chunk * chunk_size + ii,
+                                        right: jj,
+                                        distance,
+                                    });
+                                }
+                            }
+                        }
+                        pairs
+                    })
+                })
+                .collect();
+
+            handles
+                .into_iter()
+                .flat_map(|h| h.join().unwrap_or_else(|e| std::panic::resume_unwind(e)))
+                .collect()
+        });
+
+        Ok(Self { pairs })
+    }
+
+    /// Returns all pairs ordered by their left and then by their right position.
+    pub fn pairs(&self) -> &[JoinPair] {
+        &self.pairs
+    }
+
+    /// Writes all pairs as delimited text, e.g. with ```','``` as delimiter for CSV or ```'\t'```
+    /// for TSV. The first row contains the column names ```left```, ```right``` and
+    /// ```distance```.
+    pub fn write_delimited<W: Write>(&self, writer: &mut W, delimiter: char) -> io::Result<()> {
+        writeln!(writer, "left{0}right{0}distance", delimiter)?;
+        for p in &self.pairs {
+            writeln!(
+                writer,
+                "{1}{0}{2}{0}{3}",
+                delimiter, p.left, p.right, p.distance
+            )?;
+        }
+        Ok(())
+    }
+}
+
+pub(crate) fn check_bucket_kinds<'a, I>(digests: I) -> Result<(), TlshError>
+where
+    I: IntoIterator<Item = &'a Tlsh>,
+{
+    let mut iter = digests.into_iter();
+    if let Some(first) = iter.next() {
+        if let Some(other) = iter.find(|t| t.bucket_kind() != first.bucket_kind()) {
+            Err(TlshError::BucketKindMismatch {
+                left: first.bucket_kind(),
+                right: other.bucket_kind(),
+            })?
+        }
+    }
+    Ok(())
+}
+
+fn default_threads() -> usize {
+    thread::available_parallelism().map_or(1, |n| n.get())
+}
@@ -0,0 +1,371 @@
+use std::{
+    collections::BTreeMap,
+    io::{self, Read, Write},
+};
+
+use crate::{
+    cluster::medoid,
+    helper::{invalid_data, read_u64},
+    Clustering, Tlsh, TlshError,
+};
+
+const MAGIC: &[u8; 8] = b"TLSHOCL\0";
+const FORMAT_VERSION: u64 = 1;
+
+/// A cluster of an [`OnlineClusterer`].
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct Cluster {
+    representative: usize,
+    members: Vec<usize>,
+}
+
+/// Changes made by [`OnlineClusterer::maintain`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct MaintenanceReport {
+    /// Number of clusters merged into other clusters.
+    pub merged: usize,
+    /// Number of digests moved out of their cluster because they are too far from its new
+    /// representative.
+    pub reassigned: usize,
+    /// Number of clusters created for reassigned digests.
+    pub created: usize,
+}
+
+/// A clusterer which assigns digests to clusters one at a time, for corpora which grow
+/// continuously.
+///
+/// Each cluster has a representative digest. A new digest joins the cluster with the nearest
+/// representative if their distance does not exceed the threshold, and starts a new cluster with
+/// itself as representative otherwise. Since the assignment depends on the order of arrival,
+/// clusters should be maintained periodically with [`OnlineClusterer::maintain`].
+///
+/// Digests are identified by their position in the order of insertion. Cluster ids are assigned
+/// in increasing order and are never reused, so they remain valid across maintenance unless the
+/// cluster is merged into another one.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OnlineClusterer {
+    threshold: usize,
+    with_len: bool,
+    digests: Vec<Tlsh>,
+    assignments: Vec<usize>,
+    clusters: BTreeMap<usize, Cluster>,
+    next_cluster: usize,
+}
+
+impl OnlineClusterer {
+    /// Constructs an empty clusterer with the given distance threshold. ```with_len``` determines
+    /// whether the length difference is considered in all distances (see [`Tlsh::diff`]).
+    pub fn new(threshold: usize, with_len: bool) -> Self {
+        Self {
+            threshold,
+            with_len,
+            digests: Vec::new(),
+            assignments: Vec::new(),
+            clusters: BTreeMap::new(),
+            next_cluster: 0,
+        }
+    }
+
+    /// Returns the number of digests.
+    pub fn len(&self) -> usize {
+        self.digests.len()
+    }
+
+    /// Returns ```true``` if no digests have been inserted.
+    pub fn is_empty(&self) -> bool {
+        self.digests.is_empty()
+    }
+
+    /// Returns the number of clusters.
+    pub fn cluster_count(&self) -> usize {
+        self.clusters.len()
+    }
+
+    /// Returns the ids of all clusters in ascending order.
+    pub fn cluster_ids(&self) -> Vec<usize> {
+        self.clusters.keys().copied().collect()
+    }
+
+    /// Returns the cluster of the digest at position ```idx```.
+    ///
+    /// # Panics
+    /// Panics if ```idx``` is out of bounds.
+    pub fn cluster_of(&self, idx: usize) -> usize {
+        self.assignments[idx]
+    }
+
+    /// Returns the positions of all members of a cluster in order of insertion, or ```None``` if
+    /// there is no cluster with the given id.
+    pub fn members(&self, cluster: usize) -> Option<&[usize]> {
+        self.clusters.get(&cluster).map(|c| c.members.as_slice())
+    }
+
+    /// Returns the position of the representative digest of a cluster, or ```None``` if there is
+    /// no cluster with the given id.
+    pub fn representative(&self, cluster: usize) -> Option<usize> {
+        self.clusters.get(&cluster).map(|c| c.representative)
+    }
+
+    /// Returns the digest at position ```idx```.
+    ///
+    /// # Panics
+    /// Panics if ```idx``` is out of bounds.
+    pub fn digest(&self, idx: usize) -> &Tlsh {
+        &self.digests[idx]
+    }
+
+    /// Adds a digest and returns the id of the cluster it was assigned to.
+    ///
+    /// Returns an error if the digest has a different [`BucketKind`](crate::BucketKind) than the
+    /// digests inserted before.
+    pub fn insert(&mut self, tlsh: Tlsh) -> Result<usize, TlshError> {
+        if let Some(first) = self.digests.first() {
+            if first.bucket_kind() != tlsh.bucket_kind() {
+                Err(TlshError::BucketKindMismatch {
+                    left: tlsh.bucket_kind(),
+                    right: first.bucket_kind(),
+                })?
+            }
+        }
+
+        self.digests.push(tlsh);
+        self.assignments.push(0);
+        let (cluster, _) = self.assign(self.digests.len() - 1);
+
+        Ok(cluster)
+    }
+
+    /// Maintains the clusters in three steps:
+    /// 1. Clusters are visited in ascending order of their ids, and each cluster absorbs all
+    ///    clusters with a larger id whose representative lies within the threshold of its own.
+    /// 2. The representative of each cluster is recomputed as described in
+    ///    [`Clustering::representatives`].
+    /// 3. Members which are farther than the threshold from the new representative of their
+    ///    cluster are removed from it and inserted again, which may split them off into new
+    ///    clusters.
+    ///
+    /// Afterwards, every digest lies within the threshold of the representative of its cluster.
+    pub fn maintain(&mut self) -> MaintenanceReport {
+        let mut report = MaintenanceReport::default();
+
+        let ids = self.cluster_ids();
+        for (ii, &id) in ids.iter().enumerate() {
+            let rep = match self.clusters.get(&id) {
+                Some(c) => c.representative,
+                None => continue,
+            };
+
+            for &other in &ids[ii + 1..] {
+                let close = self.clusters.get(&other).is_some_and(|c| {
+                    self.digests[rep]
+                        .diff_within(
+                            &self.digests[c.representative],
+                            self.with_len,
+                            self.threshold,
+                        )
+                        .is_some()
+                });
+
+                if close {
+                    let absorbed = self
+                        .clusters
+                        .remove(&other)
+                        .map(|c| c.members)
+                        .unwrap_or_default();
+                    for &m in &absorbed {
+                        self.assignments[m] = id;
+                    }
+                    if let Some(c) = self.clusters.get_mut(&id) {
+                        c.members.extend(absorbed);
+                        c.members.sort_unstable();
+                    }
+                    report.merged += 1;
+                }
+            }
+        }
+
+        let mut detached = Vec::new();
+        for cluster in self.clusters.values_mut() {
+            let rep = medoid(&cluster.members, &self.digests, self.with_len);
+            cluster.representative = rep;
+
+            let (digests, with_len, threshold) = (&self.digests, self.with_len, self.threshold);
+            cluster.members.retain(|&m| {
+                let keep = digests[rep]
+                    .diff_within(&digests[m], with_len, threshold)
+                    .is_some();
+                if !keep {
+                    detached.push(m);
+                }
+                keep
+            });
+        }
+
+        detached.sort_unstable();
+        for m in detached {
+            let (_, created) = self.assign(m);
+            report.reassigned += 1;
+            if created {
+                report.created += 1;
+            }
+        }
+
+        report
+    }
+
+    /// Returns the current clusters as a [`Clustering`], with clusters renumbered from zero in
+    /// the order of their first member.
+    pub fn clustering(&self) -> Clustering {
+        let labels: Vec<Option<usize>> = self.assignments.iter().map(|&c| Some(c)).collect();
+        Clustering::from_labels(&labels, &self.digests, self.with_len)
+    }
+
+    /// Writes the state of the clusterer, including all digests, in a versioned binary format.
+    pub fn save<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let mut buf = Vec::new();
+        buf.extend_from_slice(MAGIC);
+        for x in &[
+            FORMAT_VERSION,
+            self.threshold as u64,
+            self.with_len as u64,
+            self.next_cluster as u64,
+            self.digests.len() as u64,
+            self.clusters.len() as u64,
+        ] {
+            buf.extend_from_slice(&x.to_le_bytes());
+        }
+
+        for (tlsh, &cluster) in self.digests.iter().zip(&self.assignments) {
+            let bytes = tlsh.to_bytes();
+            buf.push(bytes.len() as u8);
+            buf.extend_from_slice(&bytes);
+            buf.extend_from_slice(&(cluster as u64).to_le_bytes());
+        }
+
+        for (&id, cluster) in &self.clusters {
+            buf.extend_from_slice(&(id as u64).to_le_bytes());
+            buf.extend_from_slice(&(cluster.representative as u64).to_le_bytes());
+        }
+
+        writer.write_all(&buf)
+    }
+
+    /// Reads a state written by [`OnlineClusterer::save`].
+    ///
+    /// Returns an error with kind [`io::ErrorKind::InvalidData`] if the data is not a valid
+    /// state.
+    pub fn load<R: Read>(reader: &mut R) -> io::Result<Self> {
+        let mut read_exact = |len: usize| -> io::Result<Vec<u8>> {
+            let mut buf = vec![0u8; len];
+            reader
+                .read_exact(&mut buf)
+                .map_err(|_| invalid_data("Truncated clusterer state"))?;
+            Ok(buf)
+        };
+
+        if read_exact(MAGIC.len())? != MAGIC {
+            Err(invalid_data("Not a TLSH clusterer state"))?
+        }
+        if read_u64(&read_exact(8)?) != FORMAT_VERSION {
+            Err(invalid_data("Unsupported clusterer state version"))?
+        }
+
+        let header = read_exact(40)?;
+        let mut fields = header.chunks(8).map(|x| read_u64(x) as usize);
+        let mut field = || fields.next().unwrap_or_default();
+        let (threshold, with_len, next_cluster, digest_count, cluster_count) =
+            (field(), field(), field(), field(), field());
+        if with_len > 1 {
+            Err(invalid_data("Clusterer state has an invalid length flag"))?
+        }
+
+        let mut result = Self::new(threshold, with_len != 0);
+        result.next_cluster = next_cluster;
+
+        let mut assignments = BTreeMap::new();
+        for ii in 0..digest_count {
+            let len = read_exact(1)?[0] as usize;
+            let tlsh = Tlsh::from_bytes(&read_exact(len)?)?;
+            let cluster = read_u64(&read_exact(8)?) as usize;
+
+            if let Some(first) = result.digests.first() {
+                if first.bucket_kind() != tlsh.bucket_kind() {
+                    Err(invalid_data(
+                        "Clusterer state has digests of different bucket kinds",
+                    ))?
+                }
+            }
+            result.digests.push(tlsh);
+            result.assignments.push(cluster);
+            assignments.entry(cluster).or_insert_with(Vec::new).push(ii);
+        }
+
+        for _ in 0..cluster_count {
+            let id = read_u64(&read_exact(8)?) as usize;
+            let representative = read_u64(&read_exact(8)?) as usize;
+            let members = assignments
+                .remove(&id)
+                .ok_or_else(|| invalid_data("Clusterer state has an empty cluster"))?;
+
+            if id >= next_cluster || members.binary_search(&representative).is_err() {
+                Err(invalid_data("Clusterer state has an invalid cluster"))?
+            }
+            result.clusters.insert(
+                id,
+                Cluster {
+                    representative,
+                    members,
+                },
+            );
+        }
+
+        if !assignments.is_empty() {
+            Err(invalid_data("Clusterer state has digests without cluster"))?
+        }
+
+        Ok(result)
+    }
+
+    /// Assigns the digest at position ```idx``` to the cluster with the nearest representative
+    /// within the threshold, or to a new cluster. Returns the cluster id and whether the cluster
+    /// was created.
+    fn assign(&mut self, idx: usize) -> (usize, bool) {
+        let tlsh = &self.digests[idx];
+        let nearest = self
+            .clusters
+            .iter()
+            .filter_map(|(&id, c)| {
+                tlsh.diff_within(
+                    &self.digests[c.representative],
+                    self.with_len,
+                    self.threshold,
+                )
+                .map(|d| (d, id))
+            })
+            .min();
+
+        let (cluster, created) = match nearest {
+            Some((_, id)) => (id, false),
+            None => {
+                let id = self.next_cluster;
+                self.next_cluster += 1;
+                self.clusters.insert(
+                    id,
+                    Cluster {
+                        representative: idx,
+                        members: Vec::new(),
+                    },
+                );
+                (id, true)
+            }
+        };
+
+        if let Some(c) = self.clusters.get_mut(&cluster) {
+            let pos = c.members.partition_point(|&m| m < idx);
+            c.members.insert(pos, idx);
+        }
+        self.assignments[idx] = cluster;
+
+        (cluster, created)
+    }
+}
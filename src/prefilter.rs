@@ -0,0 +1,120 @@
+use std::collections::HashMap;
+
+use crate::{
+    distance::{LEN_WEIGHT, QRATIO_WEIGHT},
+    helper::{len_distance, qratio_distance},
+    BucketKind, Tlsh,
+};
+
+/// Partition key: bucket kind, L-value, q1 ratio and q2 ratio.
+type HeaderKey = (BucketKind, u8, u8, u8);
+
+/// A prefilter which partitions a collection of digests by their header values.
+///
+/// For a query and a threshold, the prefilter enumerates all partitions whose header lower bound
+/// (see [`Tlsh::header_lower_bound`]) does not exceed the threshold. Digests in other partitions
+/// are guaranteed to have a larger distance to the query, so they can be skipped without
+/// comparing their bodies. Digests are identified by the ids given on insertion.
+#[derive(Clone, Debug)]
+pub struct HeaderPrefilter {
+    with_len: bool,
+    partitions: HashMap<HeaderKey, Vec<usize>>,
+}
+
+impl HeaderPrefilter {
+    /// Constructs an empty prefilter. ```with_len``` must match the flag used when comparing
+    /// digests with [`Tlsh::diff`].
+    pub fn new(with_len: bool) -> Self {
+        Self {
+            with_len,
+            partitions: HashMap::new(),
+        }
+    }
+
+    /// Constructs a prefilter from a collection of digests. Each digest is identified by its
+    /// position in the collection.
+    pub fn from_digests<'a, I>(digests: I, with_len: bool) -> Self
+    where
+        I: IntoIterator<Item = &'a Tlsh>,
+    {
+        let mut result = Self::new(with_len);
+        for (id, tlsh) in digests.into_iter().enumerate() {
+            result.insert(id, tlsh);
+        }
+        result
+    }
+
+    /// Adds a digest with the given id to the prefilter.
+    pub fn insert(&mut self, id: usize, tlsh: &Tlsh) {
+        let key = self.key(
+            tlsh.bucket_kind(),
+            tlsh.lvalue(),
+            tlsh.q1ratio(),
+            tlsh.q2ratio(),
+        );
+        self.partitions.entry(key).or_default().push(id);
+    }
+
+    /// Returns the number of non-empty partitions.
+    pub fn partition_count(&self) -> usize {
+        self.partitions.len()
+    }
+
+    /// Returns the ids of all digests whose header lower bound to ```query``` does not exceed
+    /// ```threshold```, in ascending order. Digests with a different [`BucketKind`] than the
+    /// query are never returned.
+    ///
+    /// Every digest within distance ```threshold``` of the query is contained in the result.
+    pub fn candidates(&self, query: &Tlsh, threshold: usize) -> Vec<usize> {
+        let lvalues: Vec<(u8, usize)> = if self.with_len {
+            (0..=255u8)
+                .map(|l| {
+                    let d = len_distance(query.lvalue() as usize, l as usize, LEN_WEIGHT);
+                    (l, d)
+                })
+                .filter(|&(_, d)| d <= threshold)
+                .collect()
+        } else {
+            vec![(0, 0)]
+        };
+
+        let qratio_candidates = |q: u8| -> Vec<(u8, usize)> {
+            (0..16u8)
+                .map(|r| (r, qratio_distance(q as usize, r as usize, QRATIO_WEIGHT)))
+                .filter(|&(_, d)| d <= threshold)
+                .collect()
+        };
+        let q1ratios = qratio_candidates(query.q1ratio());
+        let q2ratios = qratio_candidates(query.q2ratio());
+
+        let mut result = Vec::new();
+        for &(l, dl) in &lvalues {
+            for &(q1, d1) in &q1ratios {
+                if dl + d1 > threshold {
+                    continue;
+                }
+
+                for &(q2, d2) in &q2ratios {
+                    if dl + d1 + d2 > threshold {
+                        continue;
+                    }
+
+                    if let Some(ids) = self.partitions.get(&(query.bucket_kind(), l, q1, q2)) {
+                        result.extend_from_slice(ids);
+                    }
+                }
+            }
+        }
+
+        result.sort_unstable();
+        result
+    }
+
+    fn key(&self, bucket_kind: BucketKind, lvalue: u8, q1ratio: u8, q2ratio: u8) -> HeaderKey {
+        if self.with_len {
+            (bucket_kind, lvalue, q1ratio, q2ratio)
+        } else {
+            (bucket_kind, 0, q1ratio, q2ratio)
+        }
+    }
+}
@@ -0,0 +1,72 @@
+use std::fmt::{self, Formatter};
+
+use serde::{
+    de::{self, SeqAccess, Visitor},
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+
+use crate::Tlsh;
+
+/// A digest is serialised as its hex-encoded string (see [`Tlsh::hash`]) in human-readable formats
+/// and as its compact binary encoding (see [`Tlsh::to_bytes`]) otherwise.
+impl Serialize for Tlsh {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        if serializer.is_human_readable() {
+            serializer.collect_str(self)
+        } else {
+            serializer.serialize_bytes(&self.to_bytes())
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Tlsh {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        if deserializer.is_human_readable() {
+            deserializer.deserialize_str(TlshVisitor)
+        } else {
+            deserializer.deserialize_bytes(TlshVisitor)
+        }
+    }
+}
+
+struct TlshVisitor;
+
+impl<'de> Visitor<'de> for TlshVisitor {
+    type Value = Tlsh;
+
+    fn expecting(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str("a TLSH hash string or its binary encoding")
+    }
+
+    fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        v.parse().map_err(E::custom)
+    }
+
+    fn visit_bytes<E>(self, v: &[u8]) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        Tlsh::from_bytes(v).map_err(E::custom)
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        while let Some(b) = seq.next_element()? {
+            bytes.push(b);
+        }
+
+        self.visit_bytes(&bytes)
+    }
+}
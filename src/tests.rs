@@ -1,7 +1,9 @@
 #![allow(unused_imports, dead_code)]
+use std::{collections::BTreeMap, convert::TryFrom, num::ParseIntError, str::FromStr};
+
 use crate::{
     tlsh::{BucketKind, ChecksumKind, TlshBuilder},
-    Tlsh, Version,
+    HashingMode, ParseMode, Tlsh, TlshError, Version,
 };
 
 fn exe_test_str(
@@ -29,6 +31,48 @@ fn exe_test_str(
     tlsh
 }
 
+/// Generates digests of pseudo-random inputs. Inputs are grouped into families of variants of the
+/// same base input so that the digests cover small as well as large distances.
+fn gen_digests(count: usize, seed: u32) -> Vec<Tlsh> {
+    let mut seed = seed;
+    let mut next = move || {
+        seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+        (seed >> 16) as usize
+    };
+
+    let mut result = Vec::with_capacity(count);
+    let mut base: Vec<u8> = Vec::new();
+    for ii in 0..count {
+        if ii % 8 == 0 {
+            let len = 256 + next() % 2048;
+            base = (0..len).map(|_| (next() % 64) as u8 + 32).collect();
+        }
+
+        let mut data = base.clone();
+        for _ in 0..(next() % (data.len() / 32)) {
+            let pos = next() % data.len();
+            data[pos] = (next() % 64) as u8 + 32;
+        }
+
+        let mut builder = TlshBuilder::new(
+            BucketKind::Bucket128,
+            ChecksumKind::OneByte,
+            Version::Version4,
+        );
+        builder.update(&data);
+        result.push(builder.build().unwrap());
+    }
+
+    result
+}
+
+/// A 256-bucket digest, i.e. one which does not match the configuration of [`gen_digests`].
+fn bucket256_digest() -> Tlsh {
+    "T109F055A00114F31B8A069219E18273306B0EC081BBDF9D070C865DC638A0D910D029AE198CC69A5A4F0F9380A9EE93F2BA2BCF4208AEA74276DC5F0BB2D34E68114848"
+        .parse()
+        .unwrap()
+}
+
 fn exe_test_diff(tlsh1: &Tlsh, tlsh2: &Tlsh, no_len_diff: usize, diff: usize) {
     assert_eq!(0, tlsh1.diff(tlsh1, true));
     assert_eq!(0, tlsh2.diff(tlsh2, true));
@@ -217,7 +261,1732 @@ fn test_parse_str() {
         "Test string 1 | 256B - 3B",
     );
 
-    let result = Tlsh::from_str(&tlsh1.hash());
+    let result = Tlsh::from_str(tlsh1.hash());
     assert!(result.is_ok());
     assert!(tlsh1 == result.unwrap());
 }
+
+#[test]
+fn test_string_48b_1b() {
+    // These digests were produced by this crate, not by a reference build with 48 buckets, and
+    // only guard against regressions.
+    let tlsh1 = exe_test_str(
+        "This is a test for Lili Diao. This is a string. Hello Hello Hello ",
+        512,
+        "T109F0504276DC5F0FB2D34E68128888",
+        BucketKind::Bucket48,
+        ChecksumKind::OneByte,
+        "Test string 1 | 48B - 1B",
+    );
+    let tlsh2 = exe_test_str(
+        "This is a test for Jon Oliver. This is a string. Hello Hello Hello ",
+        1024,
+        "T130112742728C5F0FB3D34E6912888C",
+        BucketKind::Bucket48,
+        ChecksumKind::OneByte,
+        "Test string 2 | 48B - 1B",
+    );
+    exe_test_diff(&tlsh1, &tlsh2, 103, 127);
+
+    // Checksum and length do not depend on the number of buckets, so they must match the
+    // 128-bucket digests of TLSH's test.
+    let reference = [
+        "T109F05A198CC69A5A4F0F9380A9EE93F2B927CF42089EA74276DC5F0BB2D34E68114448",
+        "T1301124198C869A5A4F0F9380A9AE92F2B9278F42089EA34272885F0FB2D34E6911444C",
+    ];
+    for (tlsh, expected) in [&tlsh1, &tlsh2].iter().zip(&reference) {
+        assert_eq!(&expected[..6], &tlsh.hash()[..6]);
+    }
+
+    let result = Tlsh::from_str(tlsh1.hash());
+    assert!(result.is_ok());
+    assert!(tlsh1 == result.unwrap());
+
+    // The reference defines 48 buckets only together with a 1-byte checksum.
+    let unsupported = TlshError::UnsupportedConfiguration {
+        bucket_kind: BucketKind::Bucket48,
+        checksum_kind: ChecksumKind::ThreeByte,
+    };
+    let data = "This is a test for Lili Diao. This is a string. Hello Hello Hello ".repeat(8);
+    assert_eq!(
+        Err(unsupported.clone()),
+        crate::hash_bytes(
+            BucketKind::Bucket48,
+            ChecksumKind::ThreeByte,
+            Version::Version4,
+            data.as_bytes()
+        )
+    );
+    let mut bytes = tlsh1.to_bytes();
+    bytes[0] |= 1 << 2;
+    bytes.splice(2..2, [0, 0]);
+    assert_eq!(Err(unsupported), Tlsh::from_bytes(&bytes));
+    let hash = format!("T109F0AAAA{}", &tlsh1.hash()[6..]);
+    assert_eq!(
+        Err(TlshError::InvalidHashLength { len: 36 }),
+        Tlsh::from_str(&hash)
+    );
+}
+
+#[test]
+fn test_find_quartiles() {
+    use crate::helper::find_quartiles;
+
+    // Simple linear congruential generator to get reproducible bucket counts.
+    let mut seed: u32 = 0x2545_f491;
+    let mut next = || {
+        seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+        (seed >> 16) % 32
+    };
+
+    for bucket_count in [48, 128, 256] {
+        for _ in 0..100 {
+            let buckets: Vec<u32> = (0..256).map(|_| next()).collect();
+            let mut sorted = buckets[0..bucket_count].to_vec();
+            sorted.sort_unstable();
+
+            let quartile = bucket_count >> 2;
+            let expected = (
+                sorted[quartile - 1],
+                sorted[2 * quartile - 1],
+                sorted[3 * quartile - 1],
+            );
+            assert_eq!(expected, find_quartiles(&buckets, bucket_count));
+        }
+    }
+}
+
+#[test]
+fn test_hashing_mode() {
+    let s = "This is a test for Lili Diao. This is a string. Hello Hello Hello ";
+    let len = 200;
+
+    let mut full_str = String::with_capacity(len as usize);
+    for ii in 0..(len - 1) {
+        full_str.push(char::from_u32(ii % 26 + 65).unwrap());
+    }
+    full_str.push(char::from_u32(0).unwrap());
+    full_str.replace_range(0..s.len(), s);
+
+    let mut builder = TlshBuilder::new(
+        BucketKind::Bucket128,
+        ChecksumKind::OneByte,
+        Version::Version4,
+    );
+    builder.update(full_str.as_bytes());
+    assert!(builder.build().is_ok());
+
+    let mut builder = builder.with_mode(HashingMode::Conservative);
+    assert!(matches!(
+        builder.build(),
+        Err(TlshError::ConservativeMinSizeNotReached { .. })
+    ));
+
+    builder.update(full_str.as_bytes());
+    assert!(builder.build().is_ok());
+
+    let mut builder = TlshBuilder::new(
+        BucketKind::Bucket128,
+        ChecksumKind::OneByte,
+        Version::Version4,
+    );
+    builder.update(&full_str.as_bytes()[0..40]);
+    assert!(matches!(
+        builder.build(),
+        Err(TlshError::MinSizeNotReached { .. })
+    ));
+}
+
+#[test]
+fn test_too_few_buckets() {
+    // A constant input only populates a handful of buckets.
+    let mut builder = TlshBuilder::new(
+        BucketKind::Bucket128,
+        ChecksumKind::OneByte,
+        Version::Version4,
+    );
+    builder.update(&[0x41; 1024]);
+    assert!(matches!(
+        builder.build(),
+        Err(TlshError::TooFewBuckets { .. })
+    ));
+
+    // A 48-bucket digest needs 18 non-zero buckets rather than more than half of them.
+    let hash48 = |pattern: &str| {
+        let data: Vec<u8> = pattern.bytes().cycle().take(64).collect();
+        crate::hash_bytes(
+            BucketKind::Bucket48,
+            ChecksumKind::OneByte,
+            Version::Version4,
+            &data,
+        )
+    };
+    assert_eq!(
+        Err(TlshError::TooFewBuckets {
+            nonzero: 17,
+            required: 17
+        }),
+        hash48("dcbazyxwvu")
+    );
+    assert!(hash48("behknqtwzc").is_ok());
+}
+
+#[test]
+fn test_concurrent_diff() {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Tlsh>();
+
+    let tlsh1 = std::sync::Arc::new(exe_test_str(
+        "This is a test for Lili Diao. This is a string. Hello Hello Hello ",
+        512,
+        "T109F05A198CC69A5A4F0F9380A9EE93F2B927CF42089EA74276DC5F0BB2D34E68114448",
+        BucketKind::Bucket128,
+        ChecksumKind::OneByte,
+        "Test string 1 | 128B - 1B",
+    ));
+    let tlsh2 = std::sync::Arc::new(exe_test_str(
+        "This is a test for Jon Oliver. This is a string. Hello Hello Hello ",
+        1024,
+        "T1301124198C869A5A4F0F9380A9AE92F2B9278F42089EA34272885F0FB2D34E6911444C",
+        BucketKind::Bucket128,
+        ChecksumKind::OneByte,
+        "Test string 2 | 128B - 1B",
+    ));
+
+    let handles: Vec<_> = (0..8)
+        .map(|_| {
+            let (t1, t2) = (tlsh1.clone(), tlsh2.clone());
+            std::thread::spawn(move || t1.diff(&t2, true))
+        })
+        .collect();
+
+    for h in handles {
+        assert_eq!(121, h.join().unwrap());
+    }
+}
+
+#[test]
+fn test_parse_errors() {
+    use std::error::Error;
+
+    let result = Tlsh::from_str("T1096463F0");
+    assert_eq!(Err(TlshError::InvalidHashLength { len: 10 }), result);
+
+    // Invalid hex digit in the first code byte.
+    let hash = "T109F05AX98CC69A5A4F0F9380A9EE93F2B927CF42089EA74276DC5F0BB2D34E68114448";
+    for mode in [ParseMode::Strict, ParseMode::Lenient] {
+        let err = Tlsh::from_str_with_mode(hash, mode).unwrap_err();
+        assert!(matches!(err, TlshError::ParseHexFailed { offset: 8, .. }));
+        assert_eq!(Some(8), err.offset());
+    }
+
+    // Signs are accepted by ```from_str_radix```, but are no hex digits.
+    let valid = "T109F05A198CC69A5A4F0F9380A9EE93F2B927CF42089EA74276DC5F0BB2D34E68114448";
+    let sign = format!("{}+{}", &valid[..6], &valid[7..]);
+    assert!(matches!(
+        Tlsh::from_str_with_mode(&sign, ParseMode::Lenient),
+        Err(TlshError::ParseHexFailed { offset: 6, .. })
+    ));
+
+    let boxed: Box<dyn Error> = Box::new(Tlsh::from_str(hash).unwrap_err());
+    assert_eq!(
+        "Can't convert hex string to integer at offset 8",
+        boxed.to_string()
+    );
+    assert!(boxed.source().unwrap().is::<ParseIntError>());
+}
+
+#[test]
+fn test_parse_mode() {
+    let hash = "T109F05A198CC69A5A4F0F9380A9EE93F2B927CF42089EA74276DC5F0BB2D34E68114448";
+    let expected = Tlsh::from_str_with_mode(hash, ParseMode::Strict).unwrap();
+
+    // Lowercase digits.
+    let lower = format!("T1{}", hash[2..].to_lowercase());
+    assert_eq!(
+        Err(TlshError::InvalidHashValue { offset: 4 }),
+        Tlsh::from_str_with_mode(&lower, ParseMode::Strict)
+    );
+    assert_eq!(
+        Ok(expected.clone()),
+        Tlsh::from_str_with_mode(&lower, ParseMode::StrictIgnoreCase)
+    );
+    assert_eq!(Ok(expected.clone()), lower.parse::<Tlsh>());
+
+    // Wrong version prefix.
+    let prefix = format!("X1{}", &hash[2..]);
+    assert_eq!(
+        Err(TlshError::InvalidHashValue { offset: 0 }),
+        Tlsh::from_str_with_mode(&prefix, ParseMode::Strict)
+    );
+    let prefix = format!("T2{}", &hash[2..]);
+    assert_eq!(
+        Err(TlshError::InvalidHashValue { offset: 1 }),
+        prefix.parse::<Tlsh>()
+    );
+    assert_eq!(
+        Ok(expected.clone()),
+        Tlsh::from_str_with_mode(&prefix, ParseMode::Lenient)
+    );
+
+    // Non-hex character in the body.
+    let body = format!("{}G{}", &hash[..40], &hash[41..]);
+    assert!(matches!(
+        body.parse::<Tlsh>(),
+        Err(TlshError::ParseHexFailed { offset: 40, .. })
+    ));
+
+    // Multi-byte characters must not cause a panic while slicing.
+    let multi = format!("T1\u{e9}{}", &hash[4..]);
+    assert_eq!(hash.len(), multi.len());
+    for mode in [
+        ParseMode::Strict,
+        ParseMode::StrictIgnoreCase,
+        ParseMode::Lenient,
+    ] {
+        assert_eq!(
+            Err(TlshError::InvalidHashValue { offset: 2 }),
+            Tlsh::from_str_with_mode(&multi, mode)
+        );
+    }
+}
+
+#[test]
+fn test_parse_never_panics() {
+    let alphabet: Vec<char> = "0123456789ABCDEFabcdefT1+- \u{e9}\u{20ac}"
+        .chars()
+        .collect();
+    let mut seed: u32 = 0x1234_5678;
+    let mut next = || {
+        seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+        (seed >> 16) as usize
+    };
+
+    for len in [30, 32, 34, 36, 70, 72, 74, 76, 134, 136, 138, 140] {
+        for _ in 0..200 {
+            let mut s = String::new();
+            while s.len() < len {
+                s.push(alphabet[next() % alphabet.len()]);
+            }
+
+            for mode in [
+                ParseMode::Strict,
+                ParseMode::StrictIgnoreCase,
+                ParseMode::Lenient,
+            ] {
+                let _ = Tlsh::from_str_with_mode(&s, mode);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_std_traits() {
+    let hash1 = "T109F05A198CC69A5A4F0F9380A9EE93F2B927CF42089EA74276DC5F0BB2D34E68114448";
+    let hash2 = "T1301124198C869A5A4F0F9380A9AE92F2B9278F42089EA34272885F0FB2D34E6911444C";
+    let hash3 = "T1096463F05A198CC69A5A4F0F9380A9EE93F2B927CF42089EA74276DC5F0BB2D34E68114448";
+
+    let tlsh1: Tlsh = hash1.parse().unwrap();
+    let tlsh2 = Tlsh::try_from(hash2).unwrap();
+    let tlsh3 = Tlsh::try_from(hash3.to_string()).unwrap();
+
+    assert_eq!(hash1, tlsh1.to_string());
+    assert_eq!(hash2, format!("{}", tlsh2));
+    assert_eq!(hash3, tlsh3.hash());
+
+    // Same configuration: ordered by hex string.
+    assert!(tlsh1 < tlsh2);
+    assert_eq!(hash1 < hash2, tlsh1 < tlsh2);
+    // One-byte checksum comes before three-byte checksum.
+    assert!(tlsh2 < tlsh3);
+
+    let mut v = vec![tlsh3.clone(), tlsh2.clone(), tlsh1.clone()];
+    v.sort();
+    assert_eq!(vec![tlsh1.clone(), tlsh2.clone(), tlsh3.clone()], v);
+
+    let mut map = BTreeMap::new();
+    map.insert(tlsh2.clone(), 2);
+    map.insert(tlsh1.clone(), 1);
+    assert_eq!(Some(&1), map.get(&tlsh1));
+    assert_eq!(Some(&tlsh1), map.keys().next());
+}
+
+#[test]
+fn test_binary_encoding() {
+    let hashes = [
+        "T109F0504276DC5F0FB2D34E68128888",
+        "T109F05A198CC69A5A4F0F9380A9EE93F2B927CF42089EA74276DC5F0BB2D34E68114448",
+        "T1096463F05A198CC69A5A4F0F9380A9EE93F2B927CF42089EA74276DC5F0BB2D34E68114448",
+        "T130112A600114F35ACA028219F14673306B1EC481BFDF8D070C865AC638A0D910D029EE1A8C869A5A4F0F9380A9AEA2F2BA2B8F8208AEA34272885F0FB2D34E6912484C",
+        "09F05A198CC69A5A4F0F9380A9EE93F2B927CF42089EA74276DC5F0BB2D34E68114448",
+    ];
+
+    for hash in &hashes {
+        let tlsh: Tlsh = hash.parse().unwrap();
+        let bytes = tlsh.to_bytes();
+
+        // The body is bit-exact with the hex form.
+        let hex: String = bytes[1..].iter().map(|b| format!("{:02X}", b)).collect();
+        assert_eq!(hash.trim_start_matches("T1"), hex);
+
+        assert_eq!(Ok(tlsh.clone()), Tlsh::from_bytes(&bytes));
+        assert_eq!(Ok(tlsh.clone()), Tlsh::from_base64(&tlsh.to_base64()));
+        assert_eq!(Ok(tlsh.clone()), Tlsh::from_base32(&tlsh.to_base32()));
+    }
+
+    // Each digest has exactly one encoding: padding bits must be zero and no character may be
+    // padding only.
+    let flip_last = |s: String, alphabet: &str| {
+        let idx = alphabet.find(s.chars().last().unwrap()).unwrap();
+        format!("{}{}", &s[..s.len() - 1], &alphabet[(idx ^ 1)..=(idx ^ 1)])
+    };
+    let tlsh: Tlsh = hashes[0].parse().unwrap();
+    let base64 = flip_last(
+        tlsh.to_base64(),
+        "ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_",
+    );
+    assert_eq!(
+        Err(TlshError::InvalidHashValue {
+            offset: base64.len() - 1
+        }),
+        Tlsh::from_base64(&base64)
+    );
+    let base32 = flip_last(tlsh.to_base32(), "ABCDEFGHIJKLMNOPQRSTUVWXYZ234567");
+    assert_eq!(
+        Err(TlshError::InvalidHashValue {
+            offset: base32.len() - 1
+        }),
+        Tlsh::from_base32(&base32)
+    );
+
+    let tlsh: Tlsh = hashes[1].parse().unwrap();
+    let base32 = format!("{}A", tlsh.to_base32());
+    assert_eq!(
+        Err(TlshError::InvalidHashValue {
+            offset: base32.len() - 1
+        }),
+        Tlsh::from_base32(&base32)
+    );
+
+    let tlsh: Tlsh = hashes[1].parse().unwrap();
+    let bytes = tlsh.to_bytes();
+    assert_eq!(36, bytes.len());
+    assert_eq!(0b1001, bytes[0]);
+    assert_eq!(48, tlsh.to_base64().len());
+
+    assert_eq!(
+        Err(TlshError::InvalidHashLength { len: 35 }),
+        Tlsh::from_bytes(&bytes[..35])
+    );
+    assert_eq!(
+        Err(TlshError::InvalidHashValue { offset: 0 }),
+        Tlsh::from_bytes(&[0x13; 36])
+    );
+    assert_eq!(
+        Err(TlshError::InvalidHashValue { offset: 3 }),
+        Tlsh::from_base64("CZD/")
+    );
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_serde() {
+    let hashes = [
+        "T109F05A198CC69A5A4F0F9380A9EE93F2B927CF42089EA74276DC5F0BB2D34E68114448",
+        "T130AEF11124198C869A5A4F0F9380A9AE92F2B9278F42089EA34272885F0FB2D34E6911444C",
+        "T109F055A00114F31B8A069219E18273306B0EC081BBDF9D070C865DC638A0D910D029AE198CC69A5A4F0F9380A9EE93F2BA2BCF4208AEA74276DC5F0BB2D34E68114848",
+        "T130AEF1112A600114F35ACA028219F14673306B1EC481BFDF8D070C865AC638A0D910D029EE1A8C869A5A4F0F9380A9AEA2F2BA2B8F8208AEA34272885F0FB2D34E6912484C",
+    ];
+
+    for hash in &hashes {
+        let tlsh: Tlsh = hash.parse().unwrap();
+
+        let json = serde_json::to_string(&tlsh).unwrap();
+        assert_eq!(format!("\"{}\"", hash), json);
+        assert_eq!(tlsh, serde_json::from_str::<Tlsh>(&json).unwrap());
+
+        let bin = bincode::serialize(&tlsh).unwrap();
+        assert_eq!(8 + tlsh.to_bytes().len(), bin.len());
+        assert_eq!(tlsh, bincode::deserialize::<Tlsh>(&bin).unwrap());
+    }
+
+    assert!(serde_json::from_str::<Tlsh>("\"T1XYZ\"").is_err());
+
+    let config = (
+        BucketKind::Bucket48,
+        ChecksumKind::ThreeByte,
+        Version::Version4,
+    );
+    let json = serde_json::to_string(&config).unwrap();
+    assert_eq!("[\"Bucket48\",\"ThreeByte\",\"Version4\"]", json);
+    assert_eq!(
+        config,
+        serde_json::from_str::<(BucketKind, ChecksumKind, Version)>(&json).unwrap()
+    );
+}
+
+#[test]
+fn test_io() {
+    use std::io::{IoSlice, Write};
+
+    let expected = "T109F05A198CC69A5A4F0F9380A9EE93F2B927CF42089EA74276DC5F0BB2D34E68114448";
+    let s = "This is a test for Lili Diao. This is a string. Hello Hello Hello ";
+    let len = 512;
+
+    let mut full_str = String::with_capacity(len as usize);
+    for ii in 0..(len - 1) {
+        full_str.push(char::from_u32(ii % 26 + 65).unwrap());
+    }
+    full_str.push(char::from_u32(0).unwrap());
+    full_str.replace_range(0..s.len(), s);
+    let bytes = full_str.as_bytes();
+
+    let tlsh = crate::hash_bytes(
+        BucketKind::Bucket128,
+        ChecksumKind::OneByte,
+        Version::Version4,
+        bytes,
+    )
+    .unwrap();
+    assert_eq!(expected, tlsh.hash());
+
+    let tlsh = Tlsh::from_reader(
+        BucketKind::Bucket128,
+        ChecksumKind::OneByte,
+        Version::Version4,
+        bytes,
+    )
+    .unwrap();
+    assert_eq!(expected, tlsh.hash());
+
+    let mut builder = TlshBuilder::new(
+        BucketKind::Bucket128,
+        ChecksumKind::OneByte,
+        Version::Version4,
+    );
+    let mut reader = bytes;
+    assert_eq!(512, std::io::copy(&mut reader, &mut builder).unwrap());
+    assert_eq!(expected, builder.build().unwrap().hash());
+
+    builder.reset();
+    let slices: Vec<_> = bytes.chunks(7).map(IoSlice::new).collect();
+    assert_eq!(512, builder.write_vectored(&slices).unwrap());
+    assert_eq!(expected, builder.build().unwrap().hash());
+
+    let err = Tlsh::from_reader(
+        BucketKind::Bucket128,
+        ChecksumKind::OneByte,
+        Version::Version4,
+        &bytes[0..10],
+    )
+    .unwrap_err();
+    assert_eq!(std::io::ErrorKind::InvalidData, err.kind());
+    assert!(matches!(
+        err.get_ref().unwrap().downcast_ref::<TlshError>(),
+        Some(TlshError::MinSizeNotReached { .. })
+    ));
+
+    let tlsh = Tlsh::from_path(
+        BucketKind::Bucket128,
+        ChecksumKind::OneByte,
+        Version::Version4,
+        "test/data/lorem_0.txt",
+    );
+    assert!(tlsh.is_ok());
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn test_async_io() {
+    use tokio::io::AsyncWriteExt;
+
+    let expected = "T109F05A198CC69A5A4F0F9380A9EE93F2B927CF42089EA74276DC5F0BB2D34E68114448";
+    let s = "This is a test for Lili Diao. This is a string. Hello Hello Hello ";
+    let len = 512;
+
+    let mut full_str = String::with_capacity(len as usize);
+    for ii in 0..(len - 1) {
+        full_str.push(char::from_u32(ii % 26 + 65).unwrap());
+    }
+    full_str.push(char::from_u32(0).unwrap());
+    full_str.replace_range(0..s.len(), s);
+    let bytes = full_str.as_bytes();
+
+    let tlsh = Tlsh::from_async_reader(
+        BucketKind::Bucket128,
+        ChecksumKind::OneByte,
+        Version::Version4,
+        bytes,
+    )
+    .await
+    .unwrap();
+    assert_eq!(expected, tlsh.hash());
+
+    // Feed the builder through a pipe in small chunks.
+    let (mut tx, mut rx) = tokio::io::duplex(16);
+    let data = bytes.to_vec();
+    let writer = tokio::spawn(async move {
+        for chunk in data.chunks(13) {
+            tx.write_all(chunk).await.unwrap();
+        }
+    });
+
+    let mut builder = TlshBuilder::new(
+        BucketKind::Bucket128,
+        ChecksumKind::OneByte,
+        Version::Version4,
+    );
+    let reader = builder.update_from_async_reader(&mut rx);
+    let (n, _) = tokio::join!(reader, writer);
+    assert_eq!(512, n.unwrap());
+    assert_eq!(expected, builder.build().unwrap().hash());
+
+    builder.reset();
+    builder.write_all(bytes).await.unwrap();
+    assert_eq!(expected, builder.build().unwrap().hash());
+}
+
+#[test]
+fn test_try_diff() {
+    let hash_128_1b = "T109F05A198CC69A5A4F0F9380A9EE93F2B927CF42089EA74276DC5F0BB2D34E68114448";
+    let hash_128_3b =
+        "T130AEF11124198C869A5A4F0F9380A9AE92F2B9278F42089EA34272885F0FB2D34E6911444C";
+    let hash_256_1b = "T109F055A00114F31B8A069219E18273306B0EC081BBDF9D070C865DC638A0D910D029AE198CC69A5A4F0F9380A9EE93F2BA2BCF4208AEA74276DC5F0BB2D34E68114848";
+
+    let tlsh1: Tlsh = hash_128_1b.parse().unwrap();
+    let tlsh2: Tlsh = hash_128_3b.parse().unwrap();
+    let tlsh3: Tlsh = hash_256_1b.parse().unwrap();
+
+    // Same as the one-byte digest pair in test_string_128b_1b.
+    assert_eq!(Ok(97), tlsh1.try_diff(&tlsh2, false));
+    assert_eq!(Ok(121), tlsh2.try_diff(&tlsh1, true));
+
+    // Projecting the three-byte checksum onto its first byte.
+    let tlsh1_3b: Tlsh =
+        "T1096463F05A198CC69A5A4F0F9380A9EE93F2B927CF42089EA74276DC5F0BB2D34E68114448"
+            .parse()
+            .unwrap();
+    assert_eq!(Ok(0), tlsh1.try_diff(&tlsh1_3b, true));
+
+    // The version only affects the prefix.
+    let original: Tlsh = hash_128_1b[2..].parse().unwrap();
+    assert_eq!(Ok(0), tlsh1.try_diff(&original, true));
+
+    assert_eq!(
+        Err(TlshError::BucketKindMismatch {
+            left: BucketKind::Bucket128,
+            right: BucketKind::Bucket256,
+        }),
+        tlsh1.try_diff(&tlsh3, true)
+    );
+    assert!(std::panic::catch_unwind(|| tlsh3.diff(&tlsh1, true)).is_err());
+}
+
+#[test]
+fn test_diff_breakdown() {
+    let tlsh1: Tlsh = "T109F05A198CC69A5A4F0F9380A9EE93F2B927CF42089EA74276DC5F0BB2D34E68114448"
+        .parse()
+        .unwrap();
+    let tlsh2: Tlsh = "T1301124198C869A5A4F0F9380A9AE92F2B9278F42089EA34272885F0FB2D34E6911444C"
+        .parse()
+        .unwrap();
+
+    for &with_len in &[true, false] {
+        let breakdown = tlsh1.diff_breakdown(&tlsh2, with_len).unwrap();
+        assert_eq!(tlsh1.diff(&tlsh2, with_len), breakdown.total());
+        assert_eq!(
+            breakdown.body,
+            breakdown.buckets.iter().map(|b| b.distance).sum::<usize>()
+        );
+        assert_eq!(128, breakdown.buckets.len());
+    }
+
+    let breakdown = tlsh1.diff_breakdown(&tlsh2, true).unwrap();
+    assert_eq!(24, breakdown.len);
+    assert_eq!(121, breakdown.total());
+    assert_eq!(97, breakdown.total() - breakdown.len);
+
+    let rendered = breakdown.render_buckets();
+    assert_eq!(128, rendered.len());
+    assert_eq!(
+        breakdown
+            .buckets
+            .iter()
+            .filter(|b| b.left != b.right)
+            .count(),
+        rendered.chars().filter(|&c| c != '.').count()
+    );
+
+    let identical = tlsh1.diff_breakdown(&tlsh1, true).unwrap();
+    assert_eq!(0, identical.total());
+    assert!(identical.render_buckets().chars().all(|c| c == '.'));
+}
+
+#[test]
+fn test_diff_options() {
+    use crate::DiffOptions;
+
+    let tlsh1: Tlsh = "T109F05A198CC69A5A4F0F9380A9EE93F2B927CF42089EA74276DC5F0BB2D34E68114448"
+        .parse()
+        .unwrap();
+    let tlsh2: Tlsh = "T1301124198C869A5A4F0F9380A9AE92F2B9278F42089EA34272885F0FB2D34E6911444C"
+        .parse()
+        .unwrap();
+    let breakdown = tlsh1.diff_breakdown(&tlsh2, true).unwrap();
+
+    let options = DiffOptions::default();
+    assert_eq!(Ok(121), tlsh1.diff_with(&tlsh2, &options));
+
+    let options = DiffOptions::new().with_len_weight(0);
+    assert_eq!(Ok(97), tlsh1.diff_with(&tlsh2, &options));
+
+    let options = DiffOptions::new().with_len_cap(10);
+    assert_eq!(Ok(107), tlsh1.diff_with(&tlsh2, &options));
+
+    let options = DiffOptions::new().with_checksum_penalty(0);
+    assert_eq!(
+        Ok(121 - breakdown.checksum),
+        tlsh1.diff_with(&tlsh2, &options)
+    );
+
+    let options = DiffOptions::new().with_qratio_weight(0);
+    assert_eq!(
+        Ok(121 - breakdown.q1ratio - breakdown.q2ratio),
+        tlsh1.diff_with(&tlsh2, &options)
+    );
+
+    let opposite = breakdown.buckets.iter().filter(|b| b.distance == 6).count();
+    let options = DiffOptions::new().with_opposite_code_distance(3);
+    assert_eq!(Ok(121 - 3 * opposite), tlsh1.diff_with(&tlsh2, &options));
+
+    let options = DiffOptions::new().with_max_distance(242);
+    assert_eq!(Ok(1.0), tlsh1.similarity(&tlsh1, &options));
+    assert_eq!(Ok(0.5), tlsh1.similarity(&tlsh2, &options));
+    assert_eq!(0.0, options.similarity(1000));
+}
+
+#[test]
+fn test_diff_within() {
+    let tlsh1: Tlsh = "T109F05A198CC69A5A4F0F9380A9EE93F2B927CF42089EA74276DC5F0BB2D34E68114448"
+        .parse()
+        .unwrap();
+    let tlsh2: Tlsh = "T1301124198C869A5A4F0F9380A9AE92F2B9278F42089EA34272885F0FB2D34E6911444C"
+        .parse()
+        .unwrap();
+
+    assert_eq!(Some(0), tlsh1.diff_within(&tlsh1, true, 0));
+    assert_eq!(Some(121), tlsh1.diff_within(&tlsh2, true, 121));
+    assert_eq!(Some(121), tlsh1.diff_within(&tlsh2, true, 500));
+    assert_eq!(None, tlsh1.diff_within(&tlsh2, true, 120));
+    assert_eq!(Some(97), tlsh1.diff_within(&tlsh2, false, 97));
+    assert_eq!(None, tlsh1.diff_within(&tlsh2, false, 96));
+    // The header alone exceeds the cutoff.
+    assert_eq!(None, tlsh1.diff_within(&tlsh2, true, 10));
+
+    for cutoff in 0..200 {
+        let expected = tlsh1.diff(&tlsh2, true);
+        let expected = if expected <= cutoff {
+            Some(expected)
+        } else {
+            None
+        };
+        assert_eq!(expected, tlsh1.diff_within(&tlsh2, true, cutoff));
+    }
+}
+
+#[test]
+fn test_header_prefilter() {
+    use crate::HeaderPrefilter;
+
+    let digests = gen_digests(200, 7);
+
+    for &with_len in &[true, false] {
+        let prefilter = HeaderPrefilter::from_digests(&digests, with_len);
+        assert!(prefilter.partition_count() > 1);
+
+        for query in digests.iter().step_by(10) {
+            for &threshold in &[0, 30, 100, 200, 400] {
+                let candidates = prefilter.candidates(query, threshold);
+
+                let expected: Vec<usize> = (0..digests.len())
+                    .filter(|&ii| query.header_lower_bound(&digests[ii], with_len) <= threshold)
+                    .collect();
+                assert_eq!(expected, candidates);
+
+                for (ii, tlsh) in digests.iter().enumerate() {
+                    assert!(query.header_lower_bound(tlsh, with_len) <= query.diff(tlsh, with_len));
+                    if query.diff(tlsh, with_len) <= threshold {
+                        assert!(candidates.binary_search(&ii).is_ok());
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_calibration() {
+    use crate::{Calibration, DistanceTable, RateEstimate};
+
+    let calibration = Calibration::new(vec![
+        (10, true),
+        (20, true),
+        (20, false),
+        (40, true),
+        (50, false),
+        (90, false),
+    ]);
+    assert_eq!(6, calibration.len());
+
+    let report = calibration.report(20);
+    assert_eq!(
+        (2, 1, 2, 1),
+        (
+            report.true_positives,
+            report.false_positives,
+            report.true_negatives,
+            report.false_negatives
+        )
+    );
+    assert!((report.detection_rate() - 2.0 / 3.0).abs() < 1e-9);
+    assert!((report.false_positive_rate() - 1.0 / 3.0).abs() < 1e-9);
+
+    let roc = calibration.roc_curve();
+    assert_eq!(
+        vec![10, 20, 40, 50, 90],
+        roc.iter().map(|r| r.threshold).collect::<Vec<_>>()
+    );
+    for r in &roc {
+        assert_eq!(calibration.report(r.threshold), *r);
+    }
+
+    assert_eq!(
+        Some(10),
+        calibration.choose_threshold(0.0).map(|r| r.threshold)
+    );
+    assert_eq!(
+        Some(40),
+        calibration.choose_threshold(0.34).map(|r| r.threshold)
+    );
+
+    let table = calibration.distance_table(BucketKind::Bucket128, ChecksumKind::OneByte, 25);
+    assert_eq!(
+        vec![24, 49, 74, 99],
+        table
+            .bands()
+            .iter()
+            .map(|b| b.max_distance)
+            .collect::<Vec<_>>()
+    );
+    let estimate = table.estimate_distance(30).unwrap();
+    assert_eq!(49, estimate.max_distance);
+    assert_eq!(
+        calibration.report(49).detection_rate(),
+        estimate.detection_rate
+    );
+    assert_eq!(None, table.estimate_distance(100));
+
+    let table = DistanceTable::new(
+        BucketKind::Bucket128,
+        ChecksumKind::OneByte,
+        vec![
+            RateEstimate {
+                max_distance: 50,
+                false_positive_rate: 0.01,
+                detection_rate: 0.5,
+            },
+            RateEstimate {
+                max_distance: 20,
+                false_positive_rate: 0.001,
+                detection_rate: 0.2,
+            },
+        ],
+    );
+    assert_eq!(Some(20), table.estimate_distance(0).map(|e| e.max_distance));
+    assert_eq!(
+        Some(50),
+        table.estimate_distance(21).map(|e| e.max_distance)
+    );
+
+    // Pairs within the same family of generated digests are labelled as similar.
+    let digests = gen_digests(32, 3);
+    let mut pairs = Vec::new();
+    for ii in 0..digests.len() {
+        for jj in (ii + 1)..digests.len() {
+            pairs.push((&digests[ii], &digests[jj], ii / 8 == jj / 8));
+        }
+    }
+    let calibration = Calibration::from_digests(pairs, true).unwrap();
+    let roc = calibration.roc_curve();
+    assert!(roc
+        .windows(2)
+        .all(|w| w[0].detection_rate() <= w[1].detection_rate()
+            && w[0].false_positive_rate() <= w[1].false_positive_rate()));
+    assert_eq!(1.0, roc.last().unwrap().detection_rate());
+
+    // Estimates for digests are only available for the configuration of the table.
+    let table = calibration.distance_table(BucketKind::Bucket128, ChecksumKind::OneByte, 10);
+    assert_eq!(
+        Ok(table.estimate_distance(digests[0].diff(&digests[1], true))),
+        table.estimate(&digests[0], &digests[1], true)
+    );
+    let other = calibration.distance_table(BucketKind::Bucket128, ChecksumKind::ThreeByte, 10);
+    assert_eq!(
+        Err(TlshError::ChecksumKindMismatch {
+            left: ChecksumKind::OneByte,
+            right: ChecksumKind::ThreeByte,
+        }),
+        other.estimate(&digests[0], &digests[1], true)
+    );
+    let other = calibration.distance_table(BucketKind::Bucket256, ChecksumKind::OneByte, 10);
+    assert!(matches!(
+        other.estimate(&digests[0], &digests[1], true),
+        Err(TlshError::BucketKindMismatch { .. })
+    ));
+}
+
+#[test]
+fn test_vptree() {
+    use crate::VpTree;
+
+    let digests = gen_digests(300, 11);
+    let queries = gen_digests(20, 12);
+
+    for &with_len in &[true, false] {
+        let tree = VpTree::new(
+            digests.iter().cloned().enumerate().map(|(ii, t)| (t, ii)),
+            with_len,
+        )
+        .unwrap();
+        assert_eq!(digests.len(), tree.len());
+
+        for query in queries.iter().chain(digests.iter().step_by(25)) {
+            let mut brute: Vec<(usize, usize)> = digests
+                .iter()
+                .enumerate()
+                .map(|(ii, t)| (query.diff(t, with_len), ii))
+                .collect();
+            brute.sort_unstable();
+
+            for &radius in &[0, 50, 150, 250] {
+                let expected: Vec<(usize, usize)> =
+                    brute.iter().copied().filter(|x| x.0 <= radius).collect();
+                let found: Vec<(usize, usize)> = tree
+                    .range(query, radius)
+                    .unwrap()
+                    .iter()
+                    .map(|n| (n.distance, *n.value))
+                    .collect();
+                assert_eq!(expected, found);
+            }
+
+            for &k in &[0, 1, 5, 20, 400] {
+                let expected: Vec<(usize, usize)> = brute.iter().copied().take(k).collect();
+                let found: Vec<(usize, usize)> = tree
+                    .nearest(query, k)
+                    .unwrap()
+                    .iter()
+                    .map(|n| (n.distance, *n.value))
+                    .collect();
+                assert_eq!(expected, found);
+            }
+        }
+    }
+
+    let empty: VpTree<()> = VpTree::new(Vec::new(), true).unwrap();
+    assert!(empty.is_empty());
+    assert!(empty.nearest(&digests[0], 3).unwrap().is_empty());
+
+    let other = bucket256_digest();
+    let tree = VpTree::new(vec![(digests[0].clone(), ())], true).unwrap();
+    assert!(tree.range(&other, 100).is_err());
+    assert!(VpTree::new(vec![(digests[0].clone(), ()), (other, ())], true).is_err());
+}
+
+#[test]
+fn test_lsh_index() {
+    use crate::{LshIndex, LshParams};
+
+    let digests = gen_digests(300, 13);
+    let threshold = 100;
+
+    let mut recalls = Vec::new();
+    for &(band_size, radius) in &[(8, 0), (4, 0), (4, 1), (2, 1), (1, 2)] {
+        let params = LshParams::new()
+            .with_band_size(band_size)
+            .with_probe_radius(radius);
+        let mut index = LshIndex::new(params, true);
+        for (ii, t) in digests.iter().enumerate() {
+            index.insert(t.clone(), ii).unwrap();
+        }
+        assert_eq!(digests.len(), index.len());
+
+        let (mut expected_total, mut found_total) = (0, 0);
+        for query in digests.iter().step_by(10) {
+            let expected: Vec<(usize, usize)> = digests
+                .iter()
+                .enumerate()
+                .map(|(ii, t)| (query.diff(t, true), ii))
+                .filter(|x| x.0 <= threshold)
+                .collect();
+
+            let (found, stats) = index.query_with_stats(query, threshold).unwrap();
+            let found: Vec<(usize, usize)> = found.iter().map(|n| (n.distance, *n.value)).collect();
+
+            // Every hit is exact and the query itself is always found.
+            assert!(found.windows(2).all(|w| w[0] < w[1]));
+            assert!(found
+                .iter()
+                .all(|x| expected.binary_search_by_key(&x.1, |e| e.1).is_ok()));
+            assert!(found.iter().any(|x| x.0 == 0));
+            assert!(stats.candidates >= found.len());
+            assert!(stats.probes >= 32 / band_size);
+
+            expected_total += expected.len();
+            found_total += found.len();
+        }
+        recalls.push(found_total as f64 / expected_total as f64);
+    }
+
+    // Smaller bands and more probes never lose recall.
+    assert!(recalls.windows(2).all(|w| w[0] <= w[1]), "{:?}", recalls);
+    assert!(recalls[recalls.len() - 1] > 0.9, "{:?}", recalls);
+
+    let other = bucket256_digest();
+    let mut index = LshIndex::new(LshParams::default(), true);
+    assert!(index.query(&other, 100).unwrap().is_empty());
+    index.insert(digests[0].clone(), ()).unwrap();
+    assert!(index.query(&other, 100).is_err());
+    assert!(index.insert(other, ()).is_err());
+}
+
+#[test]
+fn test_disk_index() {
+    use crate::{DiskIndex, IndexMetadata};
+    use std::io::{ErrorKind, Write};
+
+    let dir = std::env::temp_dir().join(format!("tlsh-disk-index-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("index.bin");
+
+    let digests = gen_digests(200, 17);
+    let meta = |ii: usize| IndexMetadata {
+        path: format!("samples/{}.bin", ii),
+        size: 1000 + ii as u64,
+        label: format!("family-{}", ii / 8),
+    };
+
+    let brute = |query: &Tlsh, threshold: usize, count: usize| -> Vec<(usize, String)> {
+        let mut result: Vec<(usize, String)> = digests[..count]
+            .iter()
+            .enumerate()
+            .map(|(ii, t)| (query.diff(t, true), meta(ii).path))
+            .filter(|x| x.0 <= threshold)
+            .collect();
+        result.sort();
+        result
+    };
+    let query = |index: &mut DiskIndex, q: &Tlsh, threshold: usize| -> Vec<(usize, String)> {
+        let hits = index.query(q, threshold, true).unwrap();
+        assert!(hits
+            .windows(2)
+            .all(|w| (w[0].distance, w[0].id) < (w[1].distance, w[1].id)));
+        let mut result: Vec<(usize, String)> = hits
+            .iter()
+            .map(|h| (h.distance, index.metadata(h.id).unwrap().path))
+            .collect();
+        result.sort();
+        result
+    };
+
+    let mut index = DiskIndex::create(&path, BucketKind::Bucket128, ChecksumKind::OneByte).unwrap();
+    assert!(index.is_empty());
+    index
+        .extend(
+            digests[..150]
+                .iter()
+                .cloned()
+                .enumerate()
+                .map(|(ii, t)| (t, meta(ii))),
+        )
+        .unwrap();
+    assert_eq!(150, index.appended_len());
+
+    // Appended entries survive reopening, compaction moves them into partitions.
+    for step in 0..3 {
+        if step == 1 {
+            index = DiskIndex::open(&path).unwrap();
+        } else if step == 2 {
+            index.compact().unwrap();
+            assert_eq!(0, index.appended_len());
+            index = DiskIndex::open(&path).unwrap();
+        }
+        assert_eq!(150, index.len());
+
+        for q in digests.iter().step_by(15) {
+            for &threshold in &[0, 50, 150] {
+                assert_eq!(brute(q, threshold, 150), query(&mut index, q, threshold));
+            }
+        }
+    }
+
+    for (ii, t) in digests.iter().enumerate().skip(150) {
+        assert_eq!(ii, index.append(t.clone(), &meta(ii)).unwrap());
+    }
+    let mut index = DiskIndex::open(&path).unwrap();
+    assert_eq!(200, index.len());
+    assert_eq!(50, index.appended_len());
+    for q in digests.iter().step_by(15) {
+        assert_eq!(brute(q, 100, 200), query(&mut index, q, 100));
+    }
+    index.compact().unwrap();
+    for id in 0..index.len() {
+        let m = index.metadata(id).unwrap();
+        let ii: usize = m.path["samples/".len()..m.path.len() - 4].parse().unwrap();
+        assert_eq!(meta(ii), m);
+        assert_eq!(digests[ii], index.digest(id).unwrap());
+    }
+
+    // Mismatching digests and corrupted files are rejected.
+    let other = bucket256_digest();
+    assert_eq!(
+        ErrorKind::InvalidData,
+        index.append(other.clone(), &meta(0)).unwrap_err().kind()
+    );
+    assert!(index.query(&other, 100, true).is_err());
+
+    // An incomplete last entry, as left by a crash during an append, is dropped on opening.
+    let len = std::fs::metadata(&path).unwrap().len();
+    index.append(digests[0].clone(), &meta(0)).unwrap();
+    let full_len = std::fs::metadata(&path).unwrap().len();
+    for &cut in &[1, full_len - len - 1] {
+        let file = std::fs::OpenOptions::new().write(true).open(&path).unwrap();
+        file.set_len(full_len - cut).unwrap();
+        let index = DiskIndex::open(&path).unwrap();
+        assert_eq!(200, index.len());
+        assert_eq!(len, std::fs::metadata(&path).unwrap().len());
+
+        let mut index = DiskIndex::open(&path).unwrap();
+        index.append(digests[0].clone(), &meta(0)).unwrap();
+        assert_eq!(201, DiskIndex::open(&path).unwrap().len());
+    }
+
+    // A read-only index skips an incomplete last entry, but never modifies the file.
+    let file = std::fs::OpenOptions::new().write(true).open(&path).unwrap();
+    file.set_len(full_len - 1).unwrap();
+    let mut index = DiskIndex::open_read_only(&path).unwrap();
+    assert_eq!(200, index.len());
+    assert_eq!(
+        digests[0],
+        index.query(&digests[0], 0, true).unwrap()[0].tlsh
+    );
+    assert_eq!(
+        ErrorKind::PermissionDenied,
+        index
+            .append(digests[0].clone(), &meta(0))
+            .unwrap_err()
+            .kind()
+    );
+    assert_eq!(
+        ErrorKind::PermissionDenied,
+        index.extend(vec![]).unwrap_err().kind()
+    );
+    assert_eq!(
+        ErrorKind::PermissionDenied,
+        index.compact().unwrap_err().kind()
+    );
+    assert_eq!(full_len - 1, std::fs::metadata(&path).unwrap().len());
+
+    let mut index = DiskIndex::open(&path).unwrap();
+    index.append(digests[0].clone(), &meta(0)).unwrap();
+
+    // A failed extend appends none of the entries.
+    let entries = vec![(digests[1].clone(), meta(1)), (other.clone(), meta(0))];
+    assert_eq!(
+        ErrorKind::InvalidData,
+        index.extend(entries).unwrap_err().kind()
+    );
+    assert_eq!(201, index.len());
+    assert_eq!(full_len, std::fs::metadata(&path).unwrap().len());
+    assert_eq!(201, DiskIndex::open(&path).unwrap().len());
+
+    // A corrupt metadata length is bounded by the file instead of being allocated.
+    let mut file = std::fs::OpenOptions::new()
+        .append(true)
+        .open(&path)
+        .unwrap();
+    file.write_all(&digests[0].to_bytes()).unwrap();
+    file.write_all(&[0; 8]).unwrap();
+    file.write_all(&u32::MAX.to_le_bytes()).unwrap();
+    assert_eq!(201, DiskIndex::open(&path).unwrap().len());
+
+    let mut file = std::fs::OpenOptions::new()
+        .append(true)
+        .open(&path)
+        .unwrap();
+    file.write_all(&other.to_bytes()).unwrap();
+    assert_eq!(
+        ErrorKind::InvalidData,
+        DiskIndex::open(&path).unwrap_err().kind()
+    );
+
+    // Compaction leaves no temporary files behind.
+    let mut index = DiskIndex::create(&path, BucketKind::Bucket128, ChecksumKind::OneByte).unwrap();
+    index.append(digests[0].clone(), &meta(0)).unwrap();
+    index.compact().unwrap();
+    assert_eq!(1, std::fs::read_dir(&dir).unwrap().count());
+
+    std::fs::write(&path, b"not an index").unwrap();
+    assert_eq!(
+        ErrorKind::InvalidData,
+        DiskIndex::open(&path).unwrap_err().kind()
+    );
+    assert_eq!(
+        ErrorKind::InvalidInput,
+        DiskIndex::create(&path, BucketKind::Bucket48, ChecksumKind::ThreeByte)
+            .unwrap_err()
+            .kind()
+    );
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_concurrent_index() {
+    use crate::{ConcurrentIndex, DiskIndex, IndexMetadata};
+
+    let digests = gen_digests(240, 19);
+    let index: ConcurrentIndex<usize> =
+        ConcurrentIndex::new(BucketKind::Bucket128, ChecksumKind::OneByte, true)
+            .with_shard_count(4);
+
+    // Writers insert all digests and remove every third one while readers query.
+    std::thread::scope(|s| {
+        for chunk in digests.chunks(60).enumerate() {
+            let index = &index;
+            s.spawn(move || {
+                let (offset, chunk) = (chunk.0 * 60, chunk.1);
+                for (ii, t) in chunk.iter().enumerate() {
+                    let id = index.insert(t.clone(), offset + ii).unwrap();
+                    if (offset + ii) % 3 == 0 {
+                        assert_eq!(Some(offset + ii), index.remove(id).map(|e| e.1));
+                        assert!(!index.contains(id));
+                    }
+                }
+            });
+        }
+        for q in digests.iter().step_by(40) {
+            let index = &index;
+            s.spawn(move || {
+                for _ in 0..5 {
+                    let hits = index.query(q, 100).unwrap();
+                    assert!(hits.iter().all(|h| h.distance == q.diff(&h.tlsh, true)));
+                }
+            });
+        }
+    });
+
+    assert_eq!(160, index.len());
+    for q in digests.iter().step_by(20) {
+        let mut expected: Vec<(usize, usize)> = digests
+            .iter()
+            .enumerate()
+            .filter(|(ii, _)| ii % 3 != 0)
+            .map(|(ii, t)| (q.diff(t, true), ii))
+            .filter(|x| x.0 <= 100)
+            .collect();
+        expected.sort_unstable();
+        let mut found: Vec<(usize, usize)> = index
+            .query(q, 100)
+            .unwrap()
+            .iter()
+            .map(|h| (h.distance, h.value))
+            .collect();
+        found.sort_unstable();
+        assert_eq!(expected, found);
+    }
+
+    let snapshot = index.snapshot();
+    assert_eq!(160, snapshot.len());
+    assert!(snapshot.windows(2).all(|w| w[0].0 < w[1].0));
+    for (id, tlsh, value) in &snapshot {
+        assert_eq!(&digests[*value], tlsh);
+        assert_eq!(Some((tlsh.clone(), *value)), index.get(*id));
+    }
+
+    let dir = std::env::temp_dir().join(format!("tlsh-concurrent-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("snapshot.bin");
+    let ids = index
+        .write_snapshot(&path, |&v| IndexMetadata {
+            path: v.to_string(),
+            ..Default::default()
+        })
+        .unwrap();
+    let mut disk = DiskIndex::open(&path).unwrap();
+    assert_eq!(160, disk.len());
+    let hits = disk.query(&digests[1], 0, true).unwrap();
+    assert_eq!("1", disk.metadata(hits[0].id).unwrap().path);
+
+    // Loading a snapshot restores all entries, with ids mapped as returned by the writer.
+    let loaded: ConcurrentIndex<usize> =
+        ConcurrentIndex::load_snapshot(&path, true, |m| m.path.parse().unwrap()).unwrap();
+    assert_eq!(160, ids.len());
+    assert_eq!(160, loaded.len());
+    for (id, &old) in ids.iter().enumerate() {
+        assert_eq!(index.get(old), loaded.get(id as u64));
+    }
+    assert_eq!(160, loaded.insert(digests[0].clone(), 0).unwrap());
+
+    // Concurrent snapshots of the same index do not interfere.
+    std::thread::scope(|s| {
+        for _ in 0..4 {
+            s.spawn(|| {
+                index
+                    .write_snapshot(&path, |&v| IndexMetadata {
+                        path: v.to_string(),
+                        ..Default::default()
+                    })
+                    .unwrap()
+            });
+        }
+    });
+    assert_eq!(160, DiskIndex::open(&path).unwrap().len());
+    assert_eq!(1, std::fs::read_dir(&dir).unwrap().count());
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    let other = bucket256_digest();
+    assert!(index.insert(other.clone(), 0).is_err());
+    assert!(index.query(&other, 100).is_err());
+    assert_eq!(None, index.remove(u64::MAX));
+}
+
+#[test]
+fn test_digest_set() {
+    use crate::DigestSet;
+
+    let mut configs = vec![(
+        BucketKind::Bucket128,
+        ChecksumKind::OneByte,
+        gen_digests(200, 23),
+    )];
+    for &(bucket, checksum) in &[
+        (BucketKind::Bucket48, ChecksumKind::OneByte),
+        (BucketKind::Bucket256, ChecksumKind::ThreeByte),
+    ] {
+        let digests = (0..60u32)
+            .map(|ii| {
+                let data: Vec<u8> = (0..1024u32)
+                    .map(|jj| (jj.wrapping_mul(ii + 7) ^ (jj >> (ii % 5))) as u8)
+                    .collect();
+                let mut builder = TlshBuilder::new(bucket, checksum, Version::Version4);
+                builder.update(&data);
+                builder.build().unwrap()
+            })
+            .collect();
+        configs.push((bucket, checksum, digests));
+    }
+
+    for (bucket, checksum, digests) in &configs {
+        let set = DigestSet::from_digests(*bucket, *checksum, digests).unwrap();
+        assert_eq!(digests.len(), set.len());
+
+        for query in digests.iter().step_by(7) {
+            for &with_len in &[true, false] {
+                for &threshold in &[0, 30, 100, 300, usize::MAX] {
+                    let expected: Vec<(usize, usize)> = digests
+                        .iter()
+                        .map(|t| query.diff(t, with_len))
+                        .enumerate()
+                        .filter(|x| x.1 <= threshold)
+                        .collect();
+                    assert_eq!(expected, set.scan(query, threshold, with_len).unwrap());
+                }
+            }
+        }
+    }
+
+    let mut set = DigestSet::new(BucketKind::Bucket128, ChecksumKind::OneByte);
+    assert!(set.is_empty());
+    assert!(set.scan(&configs[0].2[0], 100, true).unwrap().is_empty());
+    assert!(set.push(&configs[1].2[0]).is_err());
+    assert!(set.push(&configs[2].2[0]).is_err());
+    assert!(set.scan(&configs[1].2[0], 100, true).is_err());
+}
+
+#[test]
+fn test_distance_matrix() {
+    use crate::{DistanceMatrix, JoinPair, SimilarityJoin};
+
+    let digests = gen_digests(37, 29);
+
+    for &threads in &[1, 3, 8] {
+        let matrix = DistanceMatrix::with_threads(&digests, true, threads).unwrap();
+        assert_eq!(digests.len(), matrix.len());
+        assert_eq!(37 * 36 / 2, matrix.condensed().len());
+
+        let full = matrix.to_full();
+        for (x, tx) in digests.iter().enumerate() {
+            for (y, ty) in digests.iter().enumerate() {
+                assert_eq!(tx.diff(ty, true), matrix.get(x, y));
+                assert_eq!(matrix.get(x, y), full[x * digests.len() + y]);
+            }
+        }
+        assert_eq!(matrix, DistanceMatrix::new(&digests, true).unwrap());
+    }
+
+    let (left, right) = digests.split_at(10);
+    for &threads in &[1, 4, 16] {
+        let join = SimilarityJoin::with_threads(left, right, 120, false, threads).unwrap();
+        let mut expected = Vec::new();
+        for (ii, x) in left.iter().enumerate() {
+            for (jj, y) in right.iter().enumerate() {
+                let distance = x.diff(y, false);
+                if distance <= 120 {
+                    expected.push(JoinPair {
+                        left: ii,
+                        right: jj,
+                        distance,
+                    });
+                }
+            }
+        }
+        assert_eq!(expected.as_slice(), join.pairs());
+    }
+
+    let matrix = DistanceMatrix::new(&digests[..3], true).unwrap();
+    let mut csv = Vec::new();
+    matrix.write_delimited(&mut csv, ',').unwrap();
+    let expected = format!(
+        ",0,1,2\n0,0,{0},{1}\n1,{0},0,{2}\n2,{1},{2},0\n",
+        matrix.get(0, 1),
+        matrix.get(0, 2),
+        matrix.get(1, 2)
+    );
+    assert_eq!(expected, String::from_utf8(csv).unwrap());
+
+    let join = SimilarityJoin::new(&digests[..2], &digests[..2], 0, true).unwrap();
+    let mut tsv = Vec::new();
+    join.write_delimited(&mut tsv, '\t').unwrap();
+    assert_eq!(
+        "left\tright\tdistance\n0\t0\t0\n1\t1\t0\n",
+        String::from_utf8(tsv).unwrap()
+    );
+
+    assert!(DistanceMatrix::new(&[], true).unwrap().is_empty());
+    assert!(SimilarityJoin::new(&[], &digests, 100, true)
+        .unwrap()
+        .pairs()
+        .is_empty());
+
+    let other = bucket256_digest();
+    assert!(DistanceMatrix::new(&[digests[0].clone(), other.clone()], true).is_err());
+    assert!(SimilarityJoin::new(&digests, &[other], 100, true).is_err());
+}
+
+#[test]
+fn test_hac_t() {
+    use crate::HacT;
+
+    let digests = gen_digests(160, 31);
+
+    let check = |clustering: &crate::Clustering| {
+        assert_eq!(digests.len(), clustering.len());
+        assert!(clustering.noise().is_empty());
+        assert_eq!(digests.len(), clustering.sizes().iter().sum::<usize>());
+
+        let mut next = 0;
+        for (ii, label) in clustering.labels().iter().enumerate() {
+            let label = label.unwrap();
+            // Clusters are numbered in the order of their first member.
+            assert!(label <= next);
+            if label == next {
+                next += 1;
+            }
+            assert!(clustering.members(label).contains(&ii));
+        }
+        for (cluster, &rep) in clustering.representatives().iter().enumerate() {
+            assert_eq!(Some(cluster), clustering.label(rep));
+            assert_eq!(
+                clustering.sizes()[cluster],
+                clustering.members(cluster).len()
+            );
+        }
+    };
+
+    // Digests of the same family are close, digests of different families far apart.
+    let clustering = HacT::new(100, true).cluster(&digests).unwrap();
+    check(&clustering);
+    for (ii, jj) in (0..digests.len()).flat_map(|ii| (0..digests.len()).map(move |jj| (ii, jj))) {
+        if clustering.label(ii) == clustering.label(jj) {
+            assert_eq!(ii / 8, jj / 8);
+        }
+    }
+    assert!(clustering.cluster_count() < digests.len() / 2);
+    assert_eq!(clustering, HacT::new(100, true).cluster(&digests).unwrap());
+
+    // Every member lies within the cutoff of the representative of its cluster.
+    for &cutoff in &[0, 20, 50, 150, 300] {
+        let clustering = HacT::new(cutoff, false).cluster(&digests).unwrap();
+        check(&clustering);
+        for (ii, x) in digests.iter().enumerate() {
+            let rep = clustering.representatives()[clustering.label(ii).unwrap()];
+            assert!(x.diff(&digests[rep], false) <= cutoff);
+        }
+    }
+
+    // A chain of gradually changing inputs is not merged into a single cluster, unlike with
+    // single linkage, since the radius of a cluster is bounded by the cutoff.
+    let mut data: Vec<u8> = (0..2048u32).map(|ii| (ii * 7919 % 251) as u8).collect();
+    let mut chain = Vec::new();
+    for step in 0..40 {
+        for ii in 0..48 {
+            let pos = (step * 48 + ii) * 1031 % data.len();
+            data[pos] = data[pos].wrapping_add(101);
+        }
+        chain.push(
+            crate::hash_bytes(
+                BucketKind::Bucket128,
+                ChecksumKind::OneByte,
+                Version::Version4,
+                &data,
+            )
+            .unwrap(),
+        );
+    }
+    let cutoff = chain
+        .windows(2)
+        .map(|w| w[0].diff(&w[1], false))
+        .max()
+        .unwrap();
+    assert!(chain[0].diff(&chain[chain.len() - 1], false) > 2 * cutoff);
+    let clustering = HacT::new(cutoff, false).cluster(&chain).unwrap();
+    assert!(clustering.cluster_count() > 1);
+    for (ii, x) in chain.iter().enumerate() {
+        let rep = clustering.representatives()[clustering.label(ii).unwrap()];
+        assert!(x.diff(&chain[rep], false) <= cutoff);
+    }
+
+    let clustering = HacT::new(10_000, true).cluster(&digests).unwrap();
+    assert_eq!(vec![digests.len()], clustering.sizes());
+
+    assert!(HacT::new(100, true).cluster(&[]).unwrap().is_empty());
+    let other = bucket256_digest();
+    assert!(HacT::new(100, true)
+        .cluster(&[digests[0].clone(), other])
+        .is_err());
+}
+
+#[test]
+fn test_dbscan_optics() {
+    use crate::{Dbscan, Optics};
+
+    // Families of 8 digests, plus a few digests without a family.
+    let mut digests = gen_digests(96, 37);
+    digests.extend(gen_digests(40, 41).into_iter().step_by(8));
+
+    for &(epsilon, min_points) in &[(0, 1), (40, 3), (100, 4), (100, 9), (300, 20)] {
+        for &with_len in &[true, false] {
+            let dbscan = Dbscan::new(epsilon, min_points, with_len);
+            let clustering = dbscan.cluster(&digests).unwrap();
+            assert_eq!(
+                clustering,
+                dbscan.with_index(false).cluster(&digests).unwrap()
+            );
+            assert_eq!(clustering, dbscan.cluster(&digests).unwrap());
+
+            let neighbours: Vec<Vec<usize>> = digests
+                .iter()
+                .map(|x| {
+                    (0..digests.len())
+                        .filter(|&jj| x.diff(&digests[jj], with_len) <= epsilon)
+                        .collect()
+                })
+                .collect();
+            let is_core = |ii: usize| neighbours[ii].len() >= min_points;
+
+            for (ii, near) in neighbours.iter().enumerate() {
+                match clustering.label(ii) {
+                    // Core digests share the cluster with all their neighbours, unless a neighbour
+                    // is a border digest claimed by another cluster.
+                    Some(label) if is_core(ii) => {
+                        for &jj in near {
+                            if is_core(jj) {
+                                assert_eq!(Some(label), clustering.label(jj));
+                            } else {
+                                assert!(clustering.label(jj).is_some());
+                            }
+                        }
+                    }
+                    Some(label) => assert!(near
+                        .iter()
+                        .any(|&jj| is_core(jj) && clustering.label(jj) == Some(label))),
+                    None => {
+                        assert!(!is_core(ii));
+                        assert!(near.iter().all(|&jj| !is_core(jj)));
+                    }
+                }
+            }
+
+            let optics = Optics::new(epsilon + 50, min_points, with_len);
+            let ordering = optics.order(&digests).unwrap();
+            assert_eq!(ordering, optics.with_index(false).order(&digests).unwrap());
+            let mut order = ordering.order().to_vec();
+            order.sort_unstable();
+            assert_eq!((0..digests.len()).collect::<Vec<_>>(), order);
+            assert_eq!(digests.len(), ordering.reachability_plot().len());
+
+            // Core digests are clustered the same way as by DBSCAN.
+            let extracted = ordering.extract(&digests, epsilon);
+            let core: Vec<usize> = (0..digests.len()).filter(|&ii| is_core(ii)).collect();
+            for &ii in &core {
+                assert!(extracted.label(ii).is_some());
+                for &jj in &core {
+                    assert_eq!(
+                        clustering.label(ii) == clustering.label(jj),
+                        extracted.label(ii) == extracted.label(jj)
+                    );
+                }
+            }
+        }
+    }
+
+    let clustering = Dbscan::new(100, 4, true).cluster(&digests).unwrap();
+    assert_eq!(12, clustering.cluster_count());
+    assert!((96..=100).all(|ii| clustering.noise().contains(&ii)));
+    assert!(clustering.noise().len() < 8);
+
+    let clustering = Dbscan::new(100, 200, true).cluster(&digests).unwrap();
+    assert_eq!(0, clustering.cluster_count());
+    assert_eq!(digests.len(), clustering.noise().len());
+
+    let other = bucket256_digest();
+    let mixed = [digests[0].clone(), other];
+    assert!(Dbscan::new(100, 2, true).cluster(&mixed).is_err());
+    assert!(Dbscan::new(100, 2, true)
+        .with_index(false)
+        .cluster(&mixed)
+        .is_err());
+    assert!(Optics::new(100, 2, true).order(&mixed).is_err());
+}
+
+#[test]
+fn test_online_clusterer() {
+    use crate::{MaintenanceReport, OnlineClusterer};
+    use std::io::ErrorKind;
+
+    // Interleave the families so that clusters grow concurrently.
+    let families = gen_digests(120, 43);
+    let digests: Vec<Tlsh> = (0..8)
+        .flat_map(|v| families.iter().skip(v).step_by(8).cloned())
+        .collect();
+
+    let check = |clusterer: &OnlineClusterer| {
+        let mut total = 0;
+        for id in clusterer.cluster_ids() {
+            let members = clusterer.members(id).unwrap();
+            let rep = clusterer.representative(id).unwrap();
+            assert!(members.contains(&rep));
+            assert!(members.windows(2).all(|w| w[0] < w[1]));
+            for &m in members {
+                assert_eq!(id, clusterer.cluster_of(m));
+                assert!(clusterer.digest(rep).diff(clusterer.digest(m), true) <= 60);
+            }
+            total += members.len();
+        }
+        assert_eq!(clusterer.len(), total);
+    };
+
+    let mut clusterer = OnlineClusterer::new(60, true);
+    assert!(clusterer.is_empty());
+    for (ii, t) in digests.iter().enumerate() {
+        let cluster = clusterer.insert(t.clone()).unwrap();
+        assert_eq!(cluster, clusterer.cluster_of(ii));
+    }
+    assert_eq!(digests.len(), clusterer.len());
+    check(&clusterer);
+
+    let before = clusterer.cluster_count();
+    let report = clusterer.maintain();
+    check(&clusterer);
+    assert_eq!(
+        before + report.created - report.merged,
+        clusterer.cluster_count()
+    );
+    assert!(report.created <= report.reassigned);
+
+    // Maintenance is idempotent once clusters are stable.
+    let mut stable = clusterer.clone();
+    for _ in 0..5 {
+        if stable.maintain() == MaintenanceReport::default() {
+            break;
+        }
+    }
+    let mut again = stable.clone();
+    assert_eq!(MaintenanceReport::default(), again.maintain());
+    assert_eq!(stable, again);
+
+    let clustering = clusterer.clustering();
+    assert_eq!(clusterer.cluster_count(), clustering.cluster_count());
+    for ii in 0..digests.len() {
+        for jj in 0..digests.len() {
+            assert_eq!(
+                clusterer.cluster_of(ii) == clusterer.cluster_of(jj),
+                clustering.label(ii) == clustering.label(jj)
+            );
+        }
+    }
+
+    // The state survives a round trip, and the reloaded clusterer keeps assigning new digests.
+    let mut buf = Vec::new();
+    clusterer.save(&mut buf).unwrap();
+    let mut reloaded = OnlineClusterer::load(&mut buf.as_slice()).unwrap();
+    assert_eq!(clusterer, reloaded);
+    let extra = gen_digests(16, 47);
+    for t in &extra {
+        assert_eq!(
+            clusterer.insert(t.clone()).unwrap(),
+            reloaded.insert(t.clone()).unwrap()
+        );
+    }
+    assert_eq!(clusterer, reloaded);
+
+    for len in &[0, 8, 20, buf.len() - 1] {
+        assert_eq!(
+            ErrorKind::InvalidData,
+            OnlineClusterer::load(&mut &buf[..*len]).unwrap_err().kind()
+        );
+    }
+    let mut corrupted = buf.clone();
+    corrupted[0] = b'X';
+    assert!(OnlineClusterer::load(&mut corrupted.as_slice()).is_err());
+    // The length flag follows the magic, the version and the threshold.
+    let mut corrupted = buf.clone();
+    corrupted[24] = 2;
+    assert_eq!(
+        ErrorKind::InvalidData,
+        OnlineClusterer::load(&mut corrupted.as_slice())
+            .unwrap_err()
+            .kind()
+    );
+
+    let other = bucket256_digest();
+    assert!(clusterer.insert(other).is_err());
+}
+
+#[test]
+fn test_metric_lower_bound() {
+    let digests = gen_digests(60, 5);
+
+    for x in &digests {
+        for y in &digests {
+            let dxy = x.metric_lower_bound(y, true);
+            assert!(dxy <= x.diff(y, true));
+            assert_eq!(dxy, y.metric_lower_bound(x, true));
+
+            for z in digests.iter().step_by(7) {
+                assert!(x.metric_lower_bound(z, true) <= dxy + y.metric_lower_bound(z, true));
+            }
+        }
+    }
+}
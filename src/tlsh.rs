@@ -1,11 +1,32 @@
+use std::{
+    cmp::Ordering,
+    convert::TryFrom,
+    fmt::{self, Display, Formatter},
+    fs::File,
+    io::{self, BufReader, IoSlice, Read, Write},
+    path::Path,
+    str::FromStr,
+};
+
 use crate::{
+    distance::{
+        BucketDiff, DiffBreakdown, DiffOptions, CHECKSUM_PENALTY, LEN_WEIGHT,
+        OPPOSITE_CODE_DISTANCE, QRATIO_WEIGHT,
+    },
+    encoding::{decode, encode, BASE32_ALPHABET, BASE64_ALPHABET},
     helper::{
-        bit_distance, find_quartiles, l_capturing, mod_diff, pearson_hash, BUCKET_SIZE, WINDOW_SIZE,
+        bit_distance, bounded_bit_distance, code_distance, find_quartiles, l1_bit_distance,
+        l_capturing, len_distance, mod_diff, pearson_hash, qratio_distance, weighted_bit_distance,
+        BUCKET_SIZE, MIN_CONSERVATIVE_DATA_LEN, MIN_DATA_LEN, WINDOW_SIZE,
     },
     TlshError,
 };
 
-const BUCKETS_A: [BucketKind; 2] = [BucketKind::Bucket128, BucketKind::Bucket256];
+const BUCKETS_A: [BucketKind; 3] = [
+    BucketKind::Bucket48,
+    BucketKind::Bucket128,
+    BucketKind::Bucket256,
+];
 const CHECKSUM_A: [ChecksumKind; 2] = [ChecksumKind::OneByte, ChecksumKind::ThreeByte];
 const VERSION_A: [Version; 2] = [Version::Original, Version::Version4];
 
@@ -26,16 +47,29 @@ pub struct Tlsh {
 
 impl Tlsh {
     /// Try to convert a hash string. Returns an instance of [`Tlsh`] if the conversion is successful.
+    ///
+    /// This is equivalent to the [`FromStr`] implementation and accepts anything that can be
+    /// referenced as a string.
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str<T>(s: T) -> Result<Self, TlshError>
     where
         T: AsRef<str>,
     {
+        <Self as FromStr>::from_str(s.as_ref())
+    }
+
+    /// Try to convert a hash string according to the given [`ParseMode`]. Returns an instance of
+    /// [`Tlsh`] if the conversion is successful.
+    ///
+    /// This function never panics. If the string is malformed, the returned error contains the
+    /// byte offset of the first offending character.
+    pub fn from_str_with_mode(s: &str, mode: ParseMode) -> Result<Self, TlshError> {
         let (mut bucket_kind, mut checksum_kind, mut ver) = (None, None, None);
 
         'outer: for bk in &BUCKETS_A {
             for ck in &CHECKSUM_A {
                 for v in &VERSION_A {
-                    if s.as_ref().len() == hash_len(*bk, *ck, *v) {
+                    if s.len() == hash_len(*bk, *ck, *v) && check_config(*bk, *ck).is_ok() {
                         bucket_kind = Some(*bk);
                         checksum_kind = Some(*ck);
                         ver = Some(*v);
@@ -46,40 +80,58 @@ impl Tlsh {
         }
 
         if bucket_kind.is_none() {
-            Err(TlshError::InvalidHashValue)?
+            Err(TlshError::InvalidHashLength { len: s.len() })?
         }
 
+        let bytes = s.as_bytes();
         let mut offset = ver.unwrap().ver().len();
+
+        // Validates the input first so that slicing in the parsing step below never panics.
+        if let Some(pos) = bytes.iter().position(|b| !b.is_ascii()) {
+            Err(TlshError::InvalidHashValue { offset: pos })?
+        }
+
+        // Characters which are no hex digits at all are reported by the parsing step below.
+        match mode {
+            ParseMode::Lenient => {}
+            ParseMode::Strict | ParseMode::StrictIgnoreCase => {
+                if let Some(pos) = ver
+                    .unwrap()
+                    .ver()
+                    .bytes()
+                    .zip(bytes)
+                    .position(|(expected, b)| expected != *b)
+                {
+                    Err(TlshError::InvalidHashValue { offset: pos })?
+                }
+
+                if mode == ParseMode::Strict {
+                    let is_lowercase_digit = |b: &u8| (b'a'..=b'f').contains(b);
+                    if let Some(pos) = bytes[offset..].iter().position(is_lowercase_digit) {
+                        Err(TlshError::InvalidHashValue {
+                            offset: offset + pos,
+                        })?
+                    }
+                }
+            }
+        }
+
         let mut checksum = vec![0; checksum_kind.unwrap().checksum_len()];
         let mut codes = vec![0; bucket_kind.unwrap().bucket_count() >> 2];
 
-        for ii in 0..checksum.len() {
-            checksum[ii] = u8::from_str_radix(
-                &s.as_ref()[offset..(offset + 2)]
-                    .chars()
-                    .rev()
-                    .collect::<String>(),
-                16,
-            )?;
+        for c in checksum.iter_mut() {
+            *c = parse_hex_byte(s, offset, true)?;
             offset += 2;
         }
 
-        let len = usize::from_str_radix(
-            &s.as_ref()[offset..(offset + 2)]
-                .chars()
-                .rev()
-                .collect::<String>(),
-            16,
-        )?;
+        let len = parse_hex_byte(s, offset, true)? as usize;
         offset += 2;
 
-        let qratio: usize = usize::from_str_radix(&s.as_ref()[offset..(offset + 2)], 16)?;
+        let qratio = parse_hex_byte(s, offset, false)? as usize;
         offset += 2;
 
-        let clen = codes.len();
-
-        for ii in 0..clen {
-            codes[clen - ii - 1] = u8::from_str_radix(&s.as_ref()[offset..(offset + 2)], 16)?;
+        for code in codes.iter_mut().rev() {
+            *code = parse_hex_byte(s, offset, false)?;
             offset += 2;
         }
 
@@ -87,7 +139,7 @@ impl Tlsh {
             bucket_kind: bucket_kind.unwrap(),
             checksum_kind: checksum_kind.unwrap(),
             ver: ver.unwrap(),
-            checksum: checksum,
+            checksum,
             len,
             q1ratio: qratio >> 4,
             q2ratio: qratio & 0xF,
@@ -95,70 +147,480 @@ impl Tlsh {
         })
     }
 
+    /// Reads all data from a reader until EOF and computes its digest.
+    ///
+    /// If the digest cannot be built, the returned [`io::Error`] has the kind
+    /// [`io::ErrorKind::InvalidData`] and wraps the [`TlshError`].
+    pub fn from_reader<R: Read>(
+        bucket: BucketKind,
+        checksum: ChecksumKind,
+        ver: Version,
+        mut reader: R,
+    ) -> io::Result<Self> {
+        let mut builder = TlshBuilder::new(bucket, checksum, ver);
+        builder.update_from_reader(&mut reader)?;
+        Ok(builder.build()?)
+    }
+
+    /// Reads the file at the given path and computes its digest. See [`Tlsh::from_reader`].
+    pub fn from_path<P: AsRef<Path>>(
+        bucket: BucketKind,
+        checksum: ChecksumKind,
+        ver: Version,
+        path: P,
+    ) -> io::Result<Self> {
+        Self::from_reader(bucket, checksum, ver, BufReader::new(File::open(path)?))
+    }
+
     /// Computes and returns the hash value in hex-encoded string format.
+    ///
+    /// This is equivalent to calling ```to_string()``` on the [`Display`] implementation.
     pub fn hash(&self) -> String {
-        let cap = hash_len(self.bucket_kind, self.checksum_kind, self.ver);
-        let mut result = String::with_capacity(cap);
-        result.push_str(self.ver.ver());
-
-        for ii in 0..self.checksum.len() {
-            result.push_str(
-                &format!("{:02X}", self.checksum[ii])
-                    .chars()
-                    .rev()
-                    .collect::<String>(),
-            );
+        self.to_string()
+    }
+
+    /// Returns the compact binary encoding of the digest.
+    ///
+    /// The first byte is a header describing the configuration of the digest:
+    /// - bits 0-1: [`BucketKind`] (```0``` = 48, ```1``` = 128, ```2``` = 256 buckets)
+    /// - bit 2: [`ChecksumKind`] (```0``` = one byte, ```1``` = three bytes)
+    /// - bit 3: [`Version`] (```0``` = original, ```1``` = version 4)
+    /// - bits 4-7: reserved, always zero
+    ///
+    /// The header is followed by the bytes of the hex-encoded form (see [`Tlsh::hash`]) without
+    /// the version prefix, i.e. decoding the hex string yields exactly these bytes. For example,
+    /// a digest with 128 buckets and one-byte checksum takes 1 + 35 bytes.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let header = match self.bucket_kind {
+            BucketKind::Bucket48 => 0,
+            BucketKind::Bucket128 => 1,
+            BucketKind::Bucket256 => 2,
+        } | match self.checksum_kind {
+            ChecksumKind::OneByte => 0,
+            ChecksumKind::ThreeByte => 1 << 2,
+        } | match self.ver {
+            Version::Original => 0,
+            Version::Version4 => 1 << 3,
+        };
+
+        let mut result = Vec::with_capacity(binary_len(self.bucket_kind, self.checksum_kind));
+        result.push(header);
+        result.extend(self.swapped_header());
+        result.push((self.q1ratio << 4 | self.q2ratio) as u8);
+        result.extend(self.codes.iter().rev());
+
+        result
+    }
+
+    /// Converts a digest from its compact binary encoding produced by [`Tlsh::to_bytes`].
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, TlshError> {
+        let header = *bytes
+            .first()
+            .ok_or(TlshError::InvalidHashLength { len: 0 })?;
+
+        let bucket_kind = match header & 0x3 {
+            0 => BucketKind::Bucket48,
+            1 => BucketKind::Bucket128,
+            2 => BucketKind::Bucket256,
+            _ => Err(TlshError::InvalidHashValue { offset: 0 })?,
+        };
+        let checksum_kind = if header & (1 << 2) == 0 {
+            ChecksumKind::OneByte
+        } else {
+            ChecksumKind::ThreeByte
+        };
+        let ver = if header & (1 << 3) == 0 {
+            Version::Original
+        } else {
+            Version::Version4
+        };
+
+        if header >> 4 != 0 {
+            Err(TlshError::InvalidHashValue { offset: 0 })?
         }
-        result.push_str(
-            &format!("{:02X}", self.len as u32)
-                .chars()
-                .rev()
-                .collect::<String>(),
-        );
-        result.push_str(&format!("{:02X}", self.q1ratio << 4 | self.q2ratio));
+        check_config(bucket_kind, checksum_kind)?;
 
-        let len = self.codes.len();
-        for ii in 0..len {
-            result.push_str(&format!("{:02X}", self.codes[len - 1 - ii]));
+        if bytes.len() != binary_len(bucket_kind, checksum_kind) {
+            Err(TlshError::InvalidHashLength { len: bytes.len() })?
         }
 
-        result
+        let checksum_len = checksum_kind.checksum_len();
+        let checksum = bytes[1..=checksum_len]
+            .iter()
+            .map(|c| c.rotate_left(4))
+            .collect();
+        let len = bytes[checksum_len + 1].rotate_left(4) as usize;
+        let qratio = bytes[checksum_len + 2] as usize;
+        let codes = bytes[(checksum_len + 3)..].iter().rev().copied().collect();
+
+        Ok(Self {
+            bucket_kind,
+            checksum_kind,
+            ver,
+            checksum,
+            len,
+            q1ratio: qratio >> 4,
+            q2ratio: qratio & 0xF,
+            codes,
+        })
+    }
+
+    /// Returns the binary encoding (see [`Tlsh::to_bytes`]) as URL-safe base64 string without
+    /// padding.
+    pub fn to_base64(&self) -> String {
+        encode(&self.to_bytes(), BASE64_ALPHABET, 6)
+    }
+
+    /// Converts a digest from a base64 string produced by [`Tlsh::to_base64`].
+    pub fn from_base64(s: &str) -> Result<Self, TlshError> {
+        Self::from_bytes(&decode(s, BASE64_ALPHABET, 6)?)
+    }
+
+    /// Returns the binary encoding (see [`Tlsh::to_bytes`]) as base32 string without padding.
+    pub fn to_base32(&self) -> String {
+        encode(&self.to_bytes(), BASE32_ALPHABET, 5)
+    }
+
+    /// Converts a digest from a base32 string produced by [`Tlsh::to_base32`].
+    pub fn from_base32(s: &str) -> Result<Self, TlshError> {
+        Self::from_bytes(&decode(s, BASE32_ALPHABET, 5)?)
+    }
+
+    /// Returns checksum and length bytes in the nibble-swapped form in which they are encoded.
+    fn swapped_header(&self) -> impl Iterator<Item = u8> + '_ {
+        self.checksum
+            .iter()
+            .map(|c| c.rotate_left(4))
+            .chain(std::iter::once((self.len as u8).rotate_left(4)))
     }
 
     /// Calculates the difference between two TLSH values.
     ///
     /// ```with_len``` controls whether the difference in length should be also considered in the calculation.
+    ///
+    /// # Panics
+    /// Panics if both digests have a different [`BucketKind`]. See [`Tlsh::try_diff`] for a
+    /// checked version and the rules applied to digests with different configurations.
     pub fn diff(&self, other: &Tlsh, with_len: bool) -> usize {
+        match self.try_diff(other, with_len) {
+            Ok(result) => result,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    /// Calculates the difference between two TLSH values. Returns an error if the digests cannot be
+    /// compared.
+    ///
+    /// Digests with different configurations are compared according to the following rules:
+    /// - Digests with a different [`BucketKind`] are incomparable since their bodies are computed
+    ///   from different bucket sets and quartiles.
+    /// - A three-byte checksum is projected to a one-byte checksum by taking its first byte, which
+    ///   is exactly the one-byte checksum of the same input.
+    /// - The [`Version`] only determines the prefix of the hash string and is ignored.
+    pub fn try_diff(&self, other: &Tlsh, with_len: bool) -> Result<usize, TlshError> {
+        if self.bucket_kind != other.bucket_kind {
+            Err(TlshError::BucketKindMismatch {
+                left: self.bucket_kind,
+                right: other.bucket_kind,
+            })?
+        }
+
         let mut result = 0;
 
         if with_len {
-            match mod_diff(self.len, other.len, 256) {
-                x @ 0..=1 => result = x,
-                x @ _ => result = x * 12,
-            };
+            result += self.len_distance(other, LEN_WEIGHT);
+        }
+
+        result += self.q1ratio_distance(other, QRATIO_WEIGHT);
+        result += self.q2ratio_distance(other, QRATIO_WEIGHT);
+        result += self.checksum_distance(other, CHECKSUM_PENALTY);
+        result += bit_distance(&self.codes, &other.codes);
+
+        Ok(result)
+    }
+
+    /// Calculates the difference between two TLSH values if it does not exceed ```cutoff```.
+    /// Returns ```None``` as soon as the running total exceeds ```cutoff```.
+    ///
+    /// The header components are computed first since they are cheap. The body is only compared
+    /// up to the point where the cutoff is exceeded. If the result is ```Some```, it equals the
+    /// result of [`Tlsh::diff`].
+    ///
+    /// # Panics
+    /// Panics if both digests have a different [`BucketKind`], same as [`Tlsh::diff`].
+    pub fn diff_within(&self, other: &Tlsh, with_len: bool, cutoff: usize) -> Option<usize> {
+        if self.bucket_kind != other.bucket_kind {
+            panic!(
+                "{}",
+                TlshError::BucketKindMismatch {
+                    left: self.bucket_kind,
+                    right: other.bucket_kind,
+                }
+            );
+        }
+
+        let mut result = self.q1ratio_distance(other, QRATIO_WEIGHT)
+            + self.q2ratio_distance(other, QRATIO_WEIGHT)
+            + self.checksum_distance(other, CHECKSUM_PENALTY);
+
+        if with_len {
+            result += self.len_distance(other, LEN_WEIGHT);
         }
 
-        match mod_diff(self.q1ratio, other.q1ratio, 16) {
-            x @ 0..=1 => result += x,
-            x @ _ => result += (x - 1) * 12,
+        if result > cutoff {
+            return None;
         }
 
-        match mod_diff(self.q2ratio, other.q2ratio, 16) {
-            x @ 0..=1 => result += x,
-            x @ _ => result += (x - 1) * 12,
+        bounded_bit_distance(&self.codes, &other.codes, cutoff - result).map(|d| d + result)
+    }
+
+    /// Calculates the difference between two TLSH values and returns the contribution of each
+    /// component. The components sum up to the result of [`Tlsh::try_diff`].
+    pub fn diff_breakdown(&self, other: &Tlsh, with_len: bool) -> Result<DiffBreakdown, TlshError> {
+        if self.bucket_kind != other.bucket_kind {
+            Err(TlshError::BucketKindMismatch {
+                left: self.bucket_kind,
+                right: other.bucket_kind,
+            })?
         }
 
-        for ii in 0..self.checksum.len() {
-            if self.checksum[ii] != other.checksum[ii] {
-                result += 1;
-                break;
+        let mut buckets = Vec::with_capacity(self.bucket_kind.bucket_count());
+        for (&x, &y) in self.codes.iter().zip(&other.codes) {
+            for jj in 0..4 {
+                let (left, right) = ((x >> (jj * 2)) & 0x3, (y >> (jj * 2)) & 0x3);
+                buckets.push(BucketDiff {
+                    left,
+                    right,
+                    distance: code_distance(left, right),
+                });
             }
         }
 
-        result += bit_distance(&self.codes, &other.codes);
+        Ok(DiffBreakdown {
+            len: if with_len {
+                self.len_distance(other, LEN_WEIGHT)
+            } else {
+                0
+            },
+            q1ratio: self.q1ratio_distance(other, QRATIO_WEIGHT),
+            q2ratio: self.q2ratio_distance(other, QRATIO_WEIGHT),
+            checksum: self.checksum_distance(other, CHECKSUM_PENALTY),
+            body: bit_distance(&self.codes, &other.codes),
+            buckets,
+        })
+    }
+
+    /// Calculates the difference between two TLSH values with custom weights for each component.
+    /// Returns an error if the digests cannot be compared (see [`Tlsh::try_diff`]).
+    ///
+    /// With [`DiffOptions::default`], the result is the same as ```try_diff(other, true)```.
+    pub fn diff_with(&self, other: &Tlsh, options: &DiffOptions) -> Result<usize, TlshError> {
+        if self.bucket_kind != other.bucket_kind {
+            Err(TlshError::BucketKindMismatch {
+                left: self.bucket_kind,
+                right: other.bucket_kind,
+            })?
+        }
+
+        let mut len = self.len_distance(other, options.len_weight);
+        if let Some(cap) = options.len_cap {
+            len = std::cmp::min(len, cap);
+        }
+
+        let body = if options.opposite_code_distance == OPPOSITE_CODE_DISTANCE {
+            bit_distance(&self.codes, &other.codes)
+        } else {
+            weighted_bit_distance(&self.codes, &other.codes, options.opposite_code_distance)
+        };
+
+        Ok(len
+            + self.q1ratio_distance(other, options.qratio_weight)
+            + self.q2ratio_distance(other, options.qratio_weight)
+            + self.checksum_distance(other, options.checksum_penalty)
+            + body)
+    }
+
+    /// Computes the similarity between two TLSH values as a score in ```[0, 1]```. See
+    /// [`DiffOptions::similarity`] for the semantics of the score.
+    pub fn similarity(&self, other: &Tlsh, options: &DiffOptions) -> Result<f64, TlshError> {
+        Ok(options.similarity(self.diff_with(other, options)?))
+    }
+
+    /// Returns the sum of the length, q1 ratio and q2 ratio components of the difference between
+    /// two TLSH values. These components only depend on the headers and never exceed the result of
+    /// [`Tlsh::diff`], so they provide a cheap lower bound on it.
+    pub fn header_lower_bound(&self, other: &Tlsh, with_len: bool) -> usize {
+        let mut result = self.q1ratio_distance(other, QRATIO_WEIGHT)
+            + self.q2ratio_distance(other, QRATIO_WEIGHT);
+
+        if with_len {
+            result += self.len_distance(other, LEN_WEIGHT);
+        }
+
+        result
+    }
+
+    /// A metric on digests which never exceeds [`Tlsh::diff`]. Unlike [`Tlsh::diff`], it satisfies
+    /// the triangle inequality, so it can be used to prune the search space of metric indexes
+    /// without losing exactness.
+    ///
+    /// It consists of the circular differences of length and quartile ratios, the checksum
+    /// penalty on the first checksum byte and the L1 distance between the bodies. Only the first
+    /// checksum byte is considered so that the triangle inequality also holds for digests with
+    /// different [`ChecksumKind`]s.
+    pub(crate) fn metric_lower_bound(&self, other: &Tlsh, with_len: bool) -> usize {
+        let mut result = mod_diff(self.q1ratio, other.q1ratio, 16)
+            + mod_diff(self.q2ratio, other.q2ratio, 16)
+            + l1_bit_distance(&self.codes, &other.codes);
+
+        if self.checksum[0] != other.checksum[0] {
+            result += CHECKSUM_PENALTY;
+        }
+
+        if with_len {
+            result += mod_diff(self.len, other.len, 256);
+        }
 
         result
     }
+
+    /// Returns the body of the digest, i.e. the packed 2-bit codes of all buckets. The code of
+    /// bucket ```i``` is stored in bits ```2 * (i % 4)``` of byte ```i / 4```.
+    pub(crate) fn codes(&self) -> &[u8] {
+        &self.codes
+    }
+
+    /// Returns the checksum bytes of the digest.
+    pub(crate) fn checksum(&self) -> &[u8] {
+        &self.checksum
+    }
+
+    /// Returns the number of buckets of the digest.
+    pub fn bucket_kind(&self) -> BucketKind {
+        self.bucket_kind
+    }
+
+    /// Returns the length of the checksum of the digest.
+    pub fn checksum_kind(&self) -> ChecksumKind {
+        self.checksum_kind
+    }
+
+    /// Returns the version of the digest.
+    pub fn version(&self) -> Version {
+        self.ver
+    }
+
+    /// Returns the logarithmic length value (L-value) stored in the header.
+    pub fn lvalue(&self) -> u8 {
+        self.len as u8
+    }
+
+    /// Returns the q1 ratio stored in the header.
+    pub fn q1ratio(&self) -> u8 {
+        self.q1ratio as u8
+    }
+
+    /// Returns the q2 ratio stored in the header.
+    pub fn q2ratio(&self) -> u8 {
+        self.q2ratio as u8
+    }
+
+    fn len_distance(&self, other: &Tlsh, weight: usize) -> usize {
+        len_distance(self.len, other.len, weight)
+    }
+
+    fn q1ratio_distance(&self, other: &Tlsh, weight: usize) -> usize {
+        qratio_distance(self.q1ratio, other.q1ratio, weight)
+    }
+
+    fn q2ratio_distance(&self, other: &Tlsh, weight: usize) -> usize {
+        qratio_distance(self.q2ratio, other.q2ratio, weight)
+    }
+
+    fn checksum_distance(&self, other: &Tlsh, penalty: usize) -> usize {
+        // Zipping compares only the first byte if the checksum kinds differ.
+        if self
+            .checksum
+            .iter()
+            .zip(&other.checksum)
+            .any(|(x, y)| x != y)
+        {
+            penalty
+        } else {
+            0
+        }
+    }
+}
+
+impl FromStr for Tlsh {
+    type Err = TlshError;
+
+    /// Try to convert a hash string. Returns an instance of [`Tlsh`] if the conversion is successful.
+    ///
+    /// The string is parsed in [`ParseMode::StrictIgnoreCase`]. See [`Tlsh::from_str_with_mode`]
+    /// for other modes.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::from_str_with_mode(s, ParseMode::StrictIgnoreCase)
+    }
+}
+
+impl TryFrom<&str> for Tlsh {
+    type Error = TlshError;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl TryFrom<String> for Tlsh {
+    type Error = TlshError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl Display for Tlsh {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(self.ver.ver())?;
+
+        // Checksum and length are written with swapped nibbles.
+        for c in &self.checksum {
+            write!(f, "{:02X}", c.rotate_left(4))?;
+        }
+        write!(f, "{:02X}", (self.len as u8).rotate_left(4))?;
+        write!(f, "{:02X}", self.q1ratio << 4 | self.q2ratio)?;
+
+        for code in self.codes.iter().rev() {
+            write!(f, "{:02X}", code)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Digests are ordered by their configuration first, i.e. by [`BucketKind`], [`ChecksumKind`] and
+/// [`Version`] in this order. Digests sharing the same configuration are ordered lexicographically
+/// by their hex-encoded string (see [`Tlsh::hash`]).
+///
+/// Note that this ordering carries no notion of similarity. It merely allows digests to be sorted
+/// or used as keys in ordered collections.
+impl Ord for Tlsh {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.bucket_kind
+            .cmp(&other.bucket_kind)
+            .then(self.checksum_kind.cmp(&other.checksum_kind))
+            .then(self.ver.cmp(&other.ver))
+            .then_with(|| self.swapped_header().cmp(other.swapped_header()))
+            .then((self.q1ratio, self.q2ratio).cmp(&(other.q1ratio, other.q2ratio)))
+            .then_with(|| self.codes.iter().rev().cmp(other.codes.iter().rev()))
+    }
+}
+
+impl PartialOrd for Tlsh {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
 }
 
 /// A builder struct for processing input stream(s).
@@ -175,6 +637,7 @@ pub struct TlshBuilder {
     data_len: usize,
     slide_window: [u8; WINDOW_SIZE],
     ver: Version,
+    mode: HashingMode,
 }
 
 impl TlshBuilder {
@@ -195,24 +658,64 @@ impl TlshBuilder {
             data_len: 0,
             slide_window: [0; WINDOW_SIZE],
             ver,
+            mode: HashingMode::NonConservative,
         }
     }
 
+    /// Sets the hashing mode which determines the conditions an input must satisfy before a digest
+    /// can be built. By default, a builder uses [`HashingMode::NonConservative`].
+    pub fn with_mode(mut self, mode: HashingMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
     /// Computes the quartiles and constructs the digest message and returns an instance of [`Tlsh`]
     /// that has all information needed to generate a hash value.
+    ///
+    /// An error is returned if the input is too short for the selected [`HashingMode`] or if more
+    /// than half of the buckets (more than 30 of 48 for [`BucketKind::Bucket48`]) are empty, since
+    /// such an input carries too little information for a meaningful digest. [`BucketKind::Bucket48`] together with [`ChecksumKind::ThreeByte`] is
+    /// rejected with [`TlshError::UnsupportedConfiguration`].
     pub fn build(&self) -> Result<Tlsh, TlshError> {
-        if self.data_len < 50 {
-            Err(TlshError::MinSizeNotReached)?
+        check_config(self.bucket_kind, self.checksum_kind)?;
+
+        match self.mode {
+            HashingMode::NonConservative if self.data_len < MIN_DATA_LEN => {
+                Err(TlshError::MinSizeNotReached {
+                    len: self.data_len,
+                    required: MIN_DATA_LEN,
+                })?
+            }
+            HashingMode::Conservative if self.data_len < MIN_CONSERVATIVE_DATA_LEN => {
+                Err(TlshError::ConservativeMinSizeNotReached {
+                    len: self.data_len,
+                    required: MIN_CONSERVATIVE_DATA_LEN,
+                })?
+            }
+            _ => {}
+        }
+
+        let nonzero = self.buckets[0..self.bucket_count]
+            .iter()
+            .filter(|&&b| b > 0)
+            .count();
+        // The reference accepts a 48-bucket digest with 18 non-zero buckets.
+        let required = match self.bucket_kind {
+            BucketKind::Bucket48 => 17,
+            _ => self.bucket_count >> 1,
+        };
+        if nonzero <= required {
+            Err(TlshError::TooFewBuckets { nonzero, required })?
         }
 
         let (q1, q2, q3) = find_quartiles(&self.buckets, self.bucket_count);
 
         if q3 == 0 {
-            panic!("q3 = 0")
+            Err(TlshError::TooFewBuckets { nonzero, required })?
         }
 
         let mut tmp = vec![0; self.code_size];
-        for ii in 0..self.code_size {
+        for (ii, code) in tmp.iter_mut().enumerate() {
             let mut h = 0;
 
             for jj in 0..4 {
@@ -227,10 +730,10 @@ impl TlshBuilder {
                 }
             }
 
-            tmp[ii] = h;
+            *code = h;
         }
 
-        let len = l_capturing(self.data_len).unwrap();
+        let len = l_capturing(self.data_len)?;
         let q1ratio = (((q1 as f64 * 100.) / (q3 as f64)) as usize) % 16;
         let q2ratio = (((q2 as f64 * 100.) / (q3 as f64)) as usize) % 16;
 
@@ -244,7 +747,7 @@ impl TlshBuilder {
             bucket_kind: self.bucket_kind,
             checksum_kind: self.checksum_kind,
             ver: self.ver,
-            checksum: checksum,
+            checksum,
             len,
             q1ratio,
             q2ratio,
@@ -257,6 +760,11 @@ impl TlshBuilder {
         self.update_from(data, 0, data.len());
     }
 
+    /// Reads all data from a reader until EOF and processes it. Returns the number of bytes read.
+    pub fn update_from_reader<R: Read>(&mut self, reader: &mut R) -> io::Result<u64> {
+        io::copy(reader, self)
+    }
+
     /// Reads an input stream from an offset an processes it.
     ///
     /// # Parameters
@@ -272,10 +780,8 @@ impl TlshBuilder {
             (j0 + WINDOW_SIZE - 4) % WINDOW_SIZE,
         );
 
-        let mut fed_len = self.data_len;
-
-        for ii in offset..(offset + len) {
-            self.slide_window[j0] = data[ii];
+        for (fed_len, &byte) in (self.data_len..).zip(&data[offset..(offset + len)]) {
+            self.slide_window[j0] = byte;
 
             if fed_len >= 4 {
                 self.checksum = pearson_hash(
@@ -351,8 +857,6 @@ impl TlshBuilder {
                 self.buckets[r as usize] += 1;
             }
 
-            fed_len += 1;
-
             let tmp = j4;
             j4 = j3;
             j3 = j2;
@@ -368,14 +872,64 @@ impl TlshBuilder {
     pub fn reset(&mut self) {
         self.buckets.fill(0);
         self.checksum = 0;
+        self.checksum_array.fill(0);
         self.data_len = 0;
         self.slide_window.fill(0);
     }
 }
 
-/// An enum determining the number of buckets for hashing.
+/// Processes all written data as input stream. Writing never fails.
+impl Write for TlshBuilder {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.update(buf);
+        Ok(buf.len())
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        let mut len = 0;
+        for buf in bufs {
+            self.update(buf);
+            len += buf.len();
+        }
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// An enum determining how hash strings are validated while being parsed.
 #[derive(Clone, Copy, Debug, Hash, Eq, PartialEq)]
+pub enum ParseMode {
+    /// The version prefix must match the configuration inferred from the string's length and the
+    /// remaining characters must be uppercase hex digits, as produced by [`Tlsh::hash`].
+    Strict,
+    /// Same as [`ParseMode::Strict`], but lowercase hex digits are accepted as well.
+    StrictIgnoreCase,
+    /// The configuration is inferred from the string's length only and the version prefix is
+    /// skipped without being checked. This mode is meant for legacy data.
+    Lenient,
+}
+
+/// An enum determining the minimal requirements on an input stream.
+#[derive(Clone, Copy, Debug, Hash, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum HashingMode {
+    /// The input must be at least 50 bytes long.
+    NonConservative,
+    /// The input must be at least 256 bytes long.
+    Conservative,
+}
+
+/// An enum determining the number of buckets for hashing.
+#[derive(Clone, Copy, Debug, Hash, Eq, PartialEq, Ord, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum BucketKind {
+    /// Hashing with 48 buckets. This is the compact (minimal) digest of the reference implementation,
+    /// which is only defined there together with [`ChecksumKind::OneByte`]. Building or parsing a
+    /// digest with [`ChecksumKind::ThreeByte`] fails with [`TlshError::UnsupportedConfiguration`].
+    Bucket48,
     /// Hashing with 128 buckets.
     Bucket128,
     /// Hashing with 256 buckets.
@@ -386,6 +940,7 @@ impl BucketKind {
     /// Returns the number of buckets.
     pub fn bucket_count(&self) -> usize {
         match self {
+            BucketKind::Bucket48 => 48,
             BucketKind::Bucket128 => 128,
             BucketKind::Bucket256 => 256,
         }
@@ -393,7 +948,8 @@ impl BucketKind {
 }
 
 /// An enum determining the length of checksum.
-#[derive(Clone, Copy, Debug, Hash, Eq, PartialEq)]
+#[derive(Clone, Copy, Debug, Hash, Eq, PartialEq, Ord, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ChecksumKind {
     /// TLSH uses one byte for checksum. The collision rate is 1/24.
     OneByte,
@@ -411,7 +967,8 @@ impl ChecksumKind {
 }
 
 /// An enum representing the version of TLSH.
-#[derive(Clone, Copy, Debug, Hash, Eq, PartialEq)]
+#[derive(Clone, Copy, Debug, Hash, Eq, PartialEq, Ord, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Version {
     /// Original version, mapping to an empty string ```""```.
     Original,
@@ -428,6 +985,52 @@ impl Version {
     }
 }
 
+/// Computes the digest of a byte array in one shot.
+pub fn hash_bytes(
+    bucket: BucketKind,
+    checksum: ChecksumKind,
+    ver: Version,
+    data: &[u8],
+) -> Result<Tlsh, TlshError> {
+    let mut builder = TlshBuilder::new(bucket, checksum, ver);
+    builder.update(data);
+    builder.build()
+}
+
+/// Parses two hex characters of a hash string starting at ```offset```. If ```swap``` is set, the
+/// two characters are swapped before parsing.
+fn parse_hex_byte(s: &str, offset: usize, swap: bool) -> Result<u8, TlshError> {
+    // Each digit is parsed on its own, since ```from_str_radix``` accepts a leading sign.
+    let mut digits = [0u8; 2];
+    for (pos, digit) in digits.iter_mut().enumerate() {
+        let offset = offset + pos;
+        *digit = u8::from_str_radix(&s[offset..=offset], 16)
+            .map_err(|source| TlshError::ParseHexFailed { offset, source })?;
+    }
+
+    if swap {
+        digits.swap(0, 1);
+    }
+
+    Ok(digits[0] << 4 | digits[1])
+}
+
+/// Checks whether the reference implementation defines a digest format for the given bucket and
+/// checksum kinds.
+pub(crate) fn check_config(bucket: BucketKind, checksum: ChecksumKind) -> Result<(), TlshError> {
+    if let (BucketKind::Bucket48, ChecksumKind::ThreeByte) = (bucket, checksum) {
+        Err(TlshError::UnsupportedConfiguration {
+            bucket_kind: bucket,
+            checksum_kind: checksum,
+        })?
+    }
+    Ok(())
+}
+
+pub(crate) fn binary_len(bucket: BucketKind, checksum: ChecksumKind) -> usize {
+    (bucket.bucket_count() >> 2) + checksum.checksum_len() + 3
+}
+
 fn hash_len(bucket: BucketKind, checksum: ChecksumKind, ver: Version) -> usize {
     (bucket.bucket_count() >> 1) + (checksum.checksum_len() << 1) + ver.ver().len() + 4
 }
@@ -0,0 +1,253 @@
+use std::collections::BinaryHeap;
+
+use crate::{BucketKind, Tlsh, TlshError};
+
+/// A digest found by a query together with its distance to the query and its payload.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Neighbour<'a, T> {
+    /// Distance to the query as computed by [`Tlsh::diff`].
+    pub distance: usize,
+    /// The digest found.
+    pub tlsh: &'a Tlsh,
+    /// The payload attached to the digest.
+    pub value: &'a T,
+}
+
+/// A vantage-point tree for nearest-neighbour search over digests with attached payloads.
+///
+/// # Exactness
+/// [`Tlsh::diff`] does not satisfy the triangle inequality, so it cannot be used to prune a metric
+/// tree directly. Instead, the tree is built on a metric which never exceeds [`Tlsh::diff`]: the
+/// circular differences of the header values plus the L1 distance between the bodies. Subtrees are
+/// only pruned if this metric proves that all their digests are farther from the query than the
+/// current search radius, and every remaining candidate is verified with [`Tlsh::diff`]. Therefore,
+/// all queries return exactly the same results as a brute-force scan.
+#[derive(Clone, Debug)]
+pub struct VpTree<T> {
+    bucket_kind: Option<BucketKind>,
+    with_len: bool,
+    items: Vec<(Tlsh, T)>,
+    nodes: Vec<Node>,
+    root: Option<usize>,
+}
+
+/// A node of the tree. Children are split by the metric distance to the vantage point: the inside
+/// child contains all items within distance ```[inside.0, inside.1]```, the outside child all
+/// items within distance ```[outside.0, outside.1]```.
+#[derive(Clone, Debug)]
+struct Node {
+    item: usize,
+    inside: Option<usize>,
+    inside_range: (usize, usize),
+    outside: Option<usize>,
+    outside_range: (usize, usize),
+}
+
+impl<T> VpTree<T> {
+    /// Builds a tree from digests and their payloads. ```with_len``` determines whether the
+    /// length difference is considered in all distances (see [`Tlsh::diff`]).
+    ///
+    /// Returns an error if the digests have different [`BucketKind`]s.
+    pub fn new<I>(items: I, with_len: bool) -> Result<Self, TlshError>
+    where
+        I: IntoIterator<Item = (Tlsh, T)>,
+    {
+        let items: Vec<(Tlsh, T)> = items.into_iter().collect();
+
+        let bucket_kind = items.first().map(|(tlsh, _)| tlsh.bucket_kind());
+        if let Some(bk) = bucket_kind {
+            if let Some((tlsh, _)) = items.iter().find(|(tlsh, _)| tlsh.bucket_kind() != bk) {
+                Err(TlshError::BucketKindMismatch {
+                    left: bk,
+                    right: tlsh.bucket_kind(),
+                })?
+            }
+        }
+
+        let mut result = Self {
+            bucket_kind,
+            with_len,
+            items,
+            nodes: Vec::new(),
+            root: None,
+        };
+
+        let mut indices: Vec<usize> = (0..result.items.len()).collect();
+        result.root = result.build(&mut indices);
+
+        Ok(result)
+    }
+
+    /// Returns the number of digests in the tree.
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    /// Returns ```true``` if the tree contains no digests.
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// Returns all digests within distance ```radius``` of the query (inclusive), ordered by
+    /// distance and then by insertion order.
+    ///
+    /// Returns an error if the query has a different [`BucketKind`] than the digests in the tree.
+    pub fn range(&self, query: &Tlsh, radius: usize) -> Result<Vec<Neighbour<'_, T>>, TlshError> {
+        self.check_query(query)?;
+
+        let mut hits = Vec::new();
+        let mut stack: Vec<usize> = self.root.into_iter().collect();
+
+        while let Some(idx) = stack.pop() {
+            let node = &self.nodes[idx];
+            let (tlsh, _) = &self.items[node.item];
+
+            if let Some(d) = query.diff_within(tlsh, self.with_len, radius) {
+                hits.push((d, node.item));
+            }
+
+            let d = query.metric_lower_bound(tlsh, self.with_len);
+            for &(child, range) in &[
+                (node.inside, node.inside_range),
+                (node.outside, node.outside_range),
+            ] {
+                if let Some(child) = child {
+                    if lower_bound(d, range) <= radius {
+                        stack.push(child);
+                    }
+                }
+            }
+        }
+
+        hits.sort_unstable();
+        Ok(self.to_neighbours(hits))
+    }
+
+    /// Returns the ```k``` digests nearest to the query, ordered by distance and then by insertion
+    /// order. Among digests with the same distance, those inserted first are preferred.
+    ///
+    /// Returns an error if the query has a different [`BucketKind`] than the digests in the tree.
+    pub fn nearest(&self, query: &Tlsh, k: usize) -> Result<Vec<Neighbour<'_, T>>, TlshError> {
+        self.check_query(query)?;
+
+        if k == 0 {
+            return Ok(Vec::new());
+        }
+
+        // Max-heap of the best (distance, item) pairs found so far.
+        let mut best: BinaryHeap<(usize, usize)> = BinaryHeap::with_capacity(k + 1);
+        self.search_nearest(self.root, query, k, &mut best);
+
+        Ok(self.to_neighbours(best.into_sorted_vec()))
+    }
+
+    fn search_nearest(
+        &self,
+        idx: Option<usize>,
+        query: &Tlsh,
+        k: usize,
+        best: &mut BinaryHeap<(usize, usize)>,
+    ) {
+        let node = match idx {
+            Some(idx) => &self.nodes[idx],
+            None => return,
+        };
+        let (tlsh, _) = &self.items[node.item];
+
+        let radius = |best: &BinaryHeap<(usize, usize)>| {
+            if best.len() < k {
+                usize::MAX
+            } else {
+                best.peek().map_or(usize::MAX, |x| x.0)
+            }
+        };
+
+        if let Some(d) = query.diff_within(tlsh, self.with_len, radius(best)) {
+            best.push((d, node.item));
+            if best.len() > k {
+                best.pop();
+            }
+        }
+
+        // Visit the child with the smaller bound first to shrink the radius early.
+        let d = query.metric_lower_bound(tlsh, self.with_len);
+        let mut children = [
+            (lower_bound(d, node.inside_range), node.inside),
+            (lower_bound(d, node.outside_range), node.outside),
+        ];
+        children.sort_unstable_by_key(|c| c.0);
+
+        for &(bound, child) in &children {
+            if child.is_some() && bound <= radius(best) {
+                self.search_nearest(child, query, k, best);
+            }
+        }
+    }
+
+    fn build(&mut self, indices: &mut [usize]) -> Option<usize> {
+        let (&mut vantage, rest) = indices.split_first_mut()?;
+
+        let (inside, inside_range, outside, outside_range) = if rest.is_empty() {
+            (None, (0, 0), None, (0, 0))
+        } else {
+            let vp = &self.items[vantage].0;
+            let mut dist: Vec<(usize, usize)> = rest
+                .iter()
+                .map(|&ii| (vp.metric_lower_bound(&self.items[ii].0, self.with_len), ii))
+                .collect();
+            dist.sort_unstable();
+
+            let mid = dist.len() / 2;
+            let inside_range = (dist[0].0, dist[mid.saturating_sub(1)].0);
+            let outside_range = (dist[mid].0, dist[dist.len() - 1].0);
+
+            for (slot, &(_, ii)) in rest.iter_mut().zip(&dist) {
+                *slot = ii;
+            }
+            let (lo, hi) = rest.split_at_mut(mid);
+
+            (self.build(lo), inside_range, self.build(hi), outside_range)
+        };
+
+        self.nodes.push(Node {
+            item: vantage,
+            inside,
+            inside_range,
+            outside,
+            outside_range,
+        });
+
+        Some(self.nodes.len() - 1)
+    }
+
+    fn check_query(&self, query: &Tlsh) -> Result<(), TlshError> {
+        match self.bucket_kind {
+            Some(bk) if bk != query.bucket_kind() => Err(TlshError::BucketKindMismatch {
+                left: query.bucket_kind(),
+                right: bk,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    fn to_neighbours(&self, hits: Vec<(usize, usize)>) -> Vec<Neighbour<'_, T>> {
+        hits.into_iter()
+            .map(|(distance, ii)| Neighbour {
+                distance,
+                tlsh: &self.items[ii].0,
+                value: &self.items[ii].1,
+            })
+            .collect()
+    }
+}
+
+/// Lower bound on the metric distance between the query and any item of a child whose distances
+/// to the vantage point lie in ```range```, given the metric distance ```d``` between the query
+/// and the vantage point.
+fn lower_bound(d: usize, range: (usize, usize)) -> usize {
+    if d < range.0 {
+        range.0 - d
+    } else {
+        d.saturating_sub(range.1)
+    }
+}